@@ -1,9 +1,45 @@
 use std::io::Result;
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// 执行命令并返回去掉首尾空白的 stdout，失败时返回 "unknown"
+fn command_output(program: &str, args: &[&str]) -> String {
+    Command::new(program)
+        .args(args)
+        .output()
+        .ok()
+        .filter(|o| o.status.success())
+        .and_then(|o| String::from_utf8(o.stdout).ok())
+        .map(|s| s.trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
 
 fn main() -> Result<()> {
+    // sqlx::migrate! 在编译期嵌入迁移文件，变更后需重新编译
+    println!("cargo:rerun-if-changed=migrations");
+    println!("cargo:rerun-if-changed=proto");
+
+    // /version 使用的构建信息
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+    println!("cargo:rustc-env=GIT_SHA={}", command_output("git", &["rev-parse", "HEAD"]));
+    let build_timestamp = std::env::var("SOURCE_DATE_EPOCH").unwrap_or_else(|_| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs().to_string())
+            .unwrap_or_default()
+    });
+    println!("cargo:rustc-env=BUILD_TIMESTAMP={}", build_timestamp);
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    println!("cargo:rustc-env=RUSTC_VERSION={}", command_output(&rustc, &["--version"]));
+
+    // 描述符集供 gRPC reflection 使用
+    let descriptor_path = PathBuf::from(std::env::var("OUT_DIR").unwrap()).join("ankr_descriptor.bin");
     tonic_prost_build::configure()
         .build_server(true)
         .out_dir("src/pb")
+        .file_descriptor_set_path(descriptor_path)
         // 可以添加更多的配置选项来控制生成的代码
         .compile_protos(&["proto/ankr.proto"], &["proto/"])?;
     Ok(())
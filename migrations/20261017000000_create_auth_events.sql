@@ -0,0 +1,11 @@
+-- IP 绑定审计事件
+CREATE TABLE IF NOT EXISTS auth_events (
+    id BIGSERIAL PRIMARY KEY,
+    uuid_hash TEXT NOT NULL,
+    bound_ip TEXT NOT NULL,
+    attempted_ip TEXT NOT NULL,
+    event_type TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS auth_events_uuid_hash_idx ON auth_events (uuid_hash);
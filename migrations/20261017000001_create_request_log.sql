@@ -0,0 +1,15 @@
+-- 请求访问日志（用于用量分析），只保存调用方标识的哈希
+CREATE TABLE IF NOT EXISTS request_log (
+    id BIGSERIAL PRIMARY KEY,
+    identity_hash TEXT NOT NULL,
+    provider TEXT NOT NULL,
+    chain TEXT NOT NULL,
+    method TEXT NOT NULL,
+    status TEXT NOT NULL,
+    duration_ms BIGINT NOT NULL,
+    bytes BIGINT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS request_log_created_at_idx ON request_log (created_at);
+CREATE INDEX IF NOT EXISTS request_log_identity_hash_idx ON request_log (identity_hash);
@@ -0,0 +1,8 @@
+-- 按调用方（标识哈希）统计的月度请求数，用于按月计费与配额
+CREATE TABLE IF NOT EXISTS usage_monthly (
+    identity_hash TEXT NOT NULL,
+    month TEXT NOT NULL,
+    request_count BIGINT NOT NULL DEFAULT 0,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (identity_hash, month)
+);
@@ -1,26 +1,420 @@
 // src/ankr.rs
 use crate::{
+    config::HttpClientConfig,
+    db::{RequestLogEntry, record_request},
     error::{AppError, Result},
+    keypool::ApiKeyPool,
     pb::ankr::{
         AnkrAssetRequest, AnkrTxHisRequest, BlockReference, Blockchain as PbBlockchain, HotAsset,
-        HotAssetList, TransactionHistoryEntry, TxHistoryList, ankr_indexer_server::AnkrIndexer,
+        HotAssetList, TransactionHistoryEntry, TxHistoryList, TxLog, ankr_indexer_server::AnkrIndexer,
         block_reference::Kind,
     },
-    state::IndexService,
+    state::{AppState, IndexService, ResponseCache},
+    telemetry::LogSampler,
+    utils::{extract_client_cert_identity, host_allowed, normalize_address, redact},
 };
+use dashmap::DashMap;
+use futures_util::future::join_all;
+use once_cell::sync::OnceCell;
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD as BASE64_URL};
+use prost::Message;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use tonic::{Request, Response, Status};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::future::Future;
+use std::sync::{
+    Arc,
+    atomic::{AtomicUsize, Ordering},
+};
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore, mpsc};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status, metadata::MetadataValue};
+use tracing::{Instrument, Span, debug, field, info, info_span, warn};
+
+// 上游单页条目数
+const TX_PAGE_SIZE: usize = 100;
+const ASSET_PAGE_SIZE: usize = 50;
+
+// 单页请求的最大尝试次数与首次退避时间（之后每次翻倍）
+const UPSTREAM_MAX_ATTEMPTS: u32 = 3;
+const UPSTREAM_BACKOFF_BASE: Duration = Duration::from_millis(200);
+
+// 支持的链与 Ankr 多链 API 链名的唯一映射；BLOCKCHAIN_UNDEFINED 不对应任何链
+fn chain_to_ankr_name(chain: PbBlockchain) -> Option<&'static str> {
+    match chain {
+        PbBlockchain::Undefined => None,
+        PbBlockchain::Eth => Some("eth"),
+        PbBlockchain::Arbitrum => Some("arbitrum"),
+        PbBlockchain::Base => Some("base"),
+        PbBlockchain::Linea => Some("linea"),
+        PbBlockchain::Optimism => Some("optimism"),
+        PbBlockchain::EthSepolia => Some("eth_sepolia"),
+        PbBlockchain::Polygon => Some("polygon"),
+        PbBlockchain::Bsc => Some("bsc"),
+    }
+}
 
-// 辅助函数：将Blockchain枚举转换为小写字符串名称，并跳过BLOCKCHAIN_UNDEFINED
+// 辅助函数：将请求中的Blockchain枚举值转换为 Ankr 链名，跳过未知值与BLOCKCHAIN_UNDEFINED
 fn blockchain_to_str(blockchain: &i32) -> Option<String> {
-    if let Ok(pb_blockchain) = PbBlockchain::try_from(*blockchain) {
-        // 跳过BLOCKCHAIN_UNDEFINED
-        if !matches!(pb_blockchain, PbBlockchain::Undefined) {
-            // 转换为小写字符串
-            return Some(pb_blockchain.as_str_name().to_lowercase());
+    PbBlockchain::try_from(*blockchain)
+        .ok()
+        .and_then(chain_to_ankr_name)
+        .map(str::to_string)
+}
+
+// 当前支持的全部链：(proto 枚举名, Ankr 链名)，枚举值连续递增
+pub fn supported_chains() -> Vec<(&'static str, &'static str)> {
+    (0..)
+        .map_while(|i| PbBlockchain::try_from(i).ok())
+        .filter_map(|chain| Some((chain.as_str_name(), chain_to_ankr_name(chain)?)))
+        .collect()
+}
+
+// 索引服务提供的 gRPC 方法
+pub const INDEXER_METHODS: &[&str] = &[
+    "GetTransactionHistory",
+    "GetAssetBalance",
+    "StreamTransactionHistory",
+    "StreamAssetBalance",
+];
+
+// 流式响应的 channel 容量（页）：客户端读取慢时上游翻页随之暂停
+const STREAM_BUFFER_PAGES: usize = 2;
+
+// 上游 syncStatus 中的数据新鲜度信息
+#[derive(Debug, Clone)]
+struct SyncStatus {
+    lag: String,
+    status: String,
+}
+
+// 从上游响应中提取 syncStatus，缺失时返回 None
+fn parse_sync_status(resp: &Value) -> Option<SyncStatus> {
+    let sync = resp.get("syncStatus")?;
+    let field = |name: &str| match sync.get(name) {
+        Some(Value::String(s)) => s.clone(),
+        Some(v) if !v.is_null() => v.to_string(),
+        _ => String::new(),
+    };
+    Some(SyncStatus {
+        lag: field("lag"),
+        status: field("status"),
+    })
+}
+
+// 将 syncStatus 写入响应 metadata，便于客户端判断数据是否滞后而不改动消息结构
+fn attach_sync_status<T>(resp: &mut Response<T>, sync: Option<&SyncStatus>) {
+    let Some(sync) = sync else { return };
+    if let Ok(v) = MetadataValue::try_from(sync.lag.as_str()) {
+        resp.metadata_mut().insert("x-sync-lag", v);
+    }
+    if let Ok(v) = MetadataValue::try_from(sync.status.as_str()) {
+        resp.metadata_mut().insert("x-sync-status", v);
+    }
+}
+
+// 向上游发送 POST，每次调用对应一个子 span（provider / chain / method / status）
+async fn post_upstream(
+    client: &reqwest::Client,
+    endpoint: &str,
+    body: &Value,
+    op: &'static str,
+) -> Result<Value> {
+    let chain = body.get("blockchain").map(Value::to_string).unwrap_or_default();
+    let span = info_span!(
+        "upstream_request",
+        provider = "ankr",
+        method = op,
+        chain = %chain,
+        status = field::Empty,
+    );
+    send_upstream(client, endpoint, body, op).instrument(span).await
+}
+
+// 进行中的上游请求数，由 runtime_metrics_task 采样
+static UPSTREAM_IN_FLIGHT: AtomicUsize = AtomicUsize::new(0);
+
+pub fn upstream_in_flight() -> usize {
+    UPSTREAM_IN_FLIGHT.load(Ordering::Relaxed)
+}
+
+// 请求结束（含提前返回）时自动减计数
+struct InFlightGuard;
+
+impl InFlightGuard {
+    fn new() -> Self {
+        UPSTREAM_IN_FLIGHT.fetch_add(1, Ordering::Relaxed);
+        Self
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        UPSTREAM_IN_FLIGHT.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// 每个上游 host 一个信号量，限制并发请求数以免触发上游自身的限流
+// allowed_hosts 为上游域名白名单，发送前校验，防止配置被篡改后请求任意地址 (SSRF)
+struct UpstreamLimits {
+    max_concurrency: usize,
+    acquire_timeout: Duration,
+    allowed_hosts: Vec<String>,
+    hosts: DashMap<String, Arc<Semaphore>>,
+}
+
+// 启动时通过 init_upstream_limits 设置；未设置时不限制并发，也不校验域名
+static UPSTREAM_LIMITS: OnceCell<UpstreamLimits> = OnceCell::new();
+
+impl UpstreamLimits {
+    fn new(config: &HttpClientConfig) -> Self {
+        Self {
+            max_concurrency: config.max_concurrency_per_host,
+            acquire_timeout: config.acquire_timeout,
+            allowed_hosts: config.allowed_hosts.clone(),
+            hosts: DashMap::new(),
+        }
+    }
+
+    // 获取目标 host 的并发许可，等待超时返回 resource_exhausted
+    async fn acquire(&self, host: &str) -> Result<OwnedSemaphorePermit> {
+        let semaphore = self
+            .hosts
+            .entry(host.to_string())
+            .or_insert_with(|| Arc::new(Semaphore::new(self.max_concurrency)))
+            .clone();
+        match tokio::time::timeout(self.acquire_timeout, semaphore.acquire_owned()).await {
+            Ok(Ok(permit)) => Ok(permit),
+            _ => {
+                warn!(host = %host, "upstream concurrency limit reached");
+                Err(Status::resource_exhausted("too many concurrent upstream requests, retry later").into())
+            }
+        }
+    }
+}
+
+pub fn init_upstream_limits(config: &HttpClientConfig) {
+    let _ = UPSTREAM_LIMITS.set(UpstreamLimits::new(config));
+}
+
+// 解析 endpoint 的 host（小写）；无法解析时返回空串
+fn upstream_host(endpoint: &str) -> String {
+    reqwest::Url::parse(endpoint)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_ascii_lowercase))
+        .unwrap_or_default()
+}
+
+// 目标 host 不在白名单内时拒绝发送，返回 unavailable（相当于 502）
+fn check_upstream_host(host: &str, url: &str, op: &'static str) -> Result<()> {
+    let Some(limits) = UPSTREAM_LIMITS.get() else {
+        return Ok(());
+    };
+    if host_allowed(host, &limits.allowed_hosts) {
+        return Ok(());
+    }
+    warn!(op, host = %host, url = %url, "upstream host not in UPSTREAM_ALLOWED_HOSTS, request blocked");
+    Err(Status::unavailable("upstream host not allowed").into())
+}
+
+// 未调用 init_upstream_limits 时不限制并发
+async fn acquire_upstream_permit(host: &str) -> Result<Option<OwnedSemaphorePermit>> {
+    match UPSTREAM_LIMITS.get() {
+        Some(limits) => limits.acquire(host).await.map(Some),
+        None => Ok(None),
+    }
+}
+
+// 发送请求并解析 JSON；记录状态与耗时，URL 中的 API key 已脱敏
+async fn send_upstream(
+    client: &reqwest::Client,
+    endpoint: &str,
+    body: &Value,
+    op: &'static str,
+) -> Result<Value> {
+    let url = redact(endpoint);
+    let host = upstream_host(endpoint);
+    check_upstream_host(&host, &url, op)?;
+    // 许可在请求（含读取响应体）结束后释放；重试退避期间不占用许可
+    let _permit = acquire_upstream_permit(&host).await?;
+    let _in_flight = InFlightGuard::new();
+    let start = Instant::now();
+
+    let mut builder = client.post(endpoint).json(body);
+    if let Ok(request_id) = REQUEST_ID.try_with(String::clone) {
+        builder = builder.header("X-Request-ID", request_id);
+    }
+
+    let resp = match builder.send().await {
+        Ok(resp) => resp,
+        Err(e) => {
+            warn!(
+                op,
+                url = %url,
+                error = %redact(&e.to_string()),
+                elapsed_ms = start.elapsed().as_millis() as u64,
+                "upstream request failed"
+            );
+            return Err(AppError::from(e));
+        }
+    };
+
+    let status = resp.status();
+    Span::current().record("status", status.as_u16());
+    debug!(
+        op,
+        url = %url,
+        status = status.as_u16(),
+        elapsed_ms = start.elapsed().as_millis() as u64,
+        "upstream request"
+    );
+    if !status.is_success() {
+        warn!(op, url = %url, status = status.as_u16(), "upstream returned error status");
+    }
+    // 限流、鉴权失败与服务端错误作为错误返回，由调用方决定是否重试或换 key
+    if status == reqwest::StatusCode::TOO_MANY_REQUESTS
+        || status == reqwest::StatusCode::UNAUTHORIZED
+        || status == reqwest::StatusCode::FORBIDDEN
+        || status.is_server_error()
+    {
+        return Err(AppError::UpstreamStatus(status.as_u16()));
+    }
+
+    // 先读取响应体再解析：HTML 错误页或被截断的响应体返回明确的 unavailable，而非 serde 错误
+    let bytes = resp.bytes().await?;
+    serde_json::from_slice(&bytes).map_err(|e| {
+        warn!(op, url = %url, status = status.as_u16(), error = %e, "upstream returned non-JSON response");
+        Status::unavailable(format!(
+            "upstream returned non-JSON response (status {})",
+            status.as_u16()
+        ))
+        .into()
+    })
+}
+
+// 就绪探针的上游连通性检查：与 send_upstream 一样经过域名白名单与并发许可，任意 HTTP 响应即视为可达
+pub async fn probe_upstream(client: &reqwest::Client, endpoint: &str, timeout: Duration) -> Result<()> {
+    let url = redact(endpoint);
+    let host = upstream_host(endpoint);
+    check_upstream_host(&host, &url, "ready_probe")?;
+    let _permit = acquire_upstream_permit(&host).await?;
+    client.get(endpoint).timeout(timeout).send().await?;
+    Ok(())
+}
+
+// 可重试的错误：429/5xx、超时与连接失败
+fn is_retryable(e: &AppError) -> bool {
+    match e {
+        AppError::UpstreamStatus(status) => *status == 429 || *status >= 500,
+        AppError::HttpRequest(e) => e.is_timeout() || e.is_connect(),
+        _ => false,
+    }
+}
+
+// post_upstream + 指数退避重试；每次尝试从 key 池重新选 key，返回 429 的 key 进入冷却
+// 401/403（key 过期或轮换中）时该 key 进入冷却，并立即换下一个 key 重试一次
+async fn post_upstream_with_retry(
+    client: &reqwest::Client,
+    keys: &ApiKeyPool,
+    body: &Value,
+    op: &'static str,
+) -> Result<Value> {
+    let mut attempt = 1;
+    let mut auth_retried = false;
+    loop {
+        let key = keys.next_key();
+        let endpoint = keys.endpoint(key);
+        let result = post_upstream(client, &endpoint, body, op).await;
+        match result {
+            Err(AppError::UpstreamStatus(429)) => keys.mark_rate_limited(key),
+            Err(AppError::UpstreamStatus(401 | 403)) => keys.mark_auth_failed(key),
+            _ => {}
+        }
+        match result {
+            Err(AppError::UpstreamStatus(401 | 403)) if !auth_retried && keys.key_count() > 1 => {
+                debug!(op, "upstream rejected API key, retrying with the next key");
+                auth_retried = true;
+            }
+            Err(e) if attempt < UPSTREAM_MAX_ATTEMPTS && is_retryable(&e) => {
+                let backoff = UPSTREAM_BACKOFF_BASE * 2u32.pow(attempt - 1);
+                debug!(op, attempt, backoff_ms = backoff.as_millis() as u64, "retrying upstream request");
+                tokio::time::sleep(backoff).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+// 区块号上限：JSON 安全整数范围（2^53 - 1），超出后上游按浮点解析会丢失精度
+const MAX_BLOCK_NUMBER: u64 = (1 << 53) - 1;
+// 时间戳最多允许超前当前时间一天（容忍时钟偏差），更大的值多半是误传的毫秒时间戳
+const MAX_TIMESTAMP_SKEW_SECS: u64 = 86_400;
+
+// 校验 BlockReference：数值须在合理范围内，latest / earliest 的取值须与名称一致
+fn validate_block_ref(
+    field: &str,
+    br: &BlockReference,
+    is_timestamp: bool,
+) -> std::result::Result<(), Status> {
+    match &br.kind {
+        Some(Kind::Number(n)) if is_timestamp => {
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default();
+            if *n > now + MAX_TIMESTAMP_SKEW_SECS {
+                return Err(Status::invalid_argument(format!(
+                    "{field} {n} is not a plausible unix timestamp in seconds"
+                )));
+            }
+        }
+        Some(Kind::Number(n)) if *n > MAX_BLOCK_NUMBER => {
+            return Err(Status::invalid_argument(format!(
+                "{field} {n} exceeds the maximum block number {MAX_BLOCK_NUMBER}"
+            )));
+        }
+        Some(Kind::Latest(v)) if v != "latest" => {
+            return Err(Status::invalid_argument(format!("{field}.latest must be \"latest\"")));
+        }
+        Some(Kind::Earliest(v)) if v != "earliest" => {
+            return Err(Status::invalid_argument(format!(
+                "{field}.earliest must be \"earliest\""
+            )));
         }
+        _ => {}
     }
-    None
+    Ok(())
+}
+
+// 用于比较范围先后的排序键：earliest < 具体数值 < latest（未设置 kind 时按 latest 处理，与 block_ref_to_json 一致）
+fn block_ref_rank(br: &BlockReference) -> (u8, u64) {
+    match &br.kind {
+        Some(Kind::Earliest(_)) => (0, 0),
+        Some(Kind::Number(n)) => (1, *n),
+        Some(Kind::Latest(_)) | None => (2, 0),
+    }
+}
+
+// from 与 to 同时给出时要求 from <= to，避免上游静默返回空结果
+fn validate_range_order(
+    from_field: &str,
+    from: Option<&BlockReference>,
+    to_field: &str,
+    to: Option<&BlockReference>,
+) -> std::result::Result<(), Status> {
+    if let (Some(from), Some(to)) = (from, to)
+        && block_ref_rank(from) > block_ref_rank(to)
+    {
+        return Err(Status::invalid_argument(format!(
+            "{from_field} ({}) must not be after {to_field} ({})",
+            block_ref_to_json(from),
+            block_ref_to_json(to)
+        )));
+    }
+    Ok(())
 }
 
 fn block_ref_to_json(br: &BlockReference) -> Value {
@@ -32,188 +426,781 @@ fn block_ref_to_json(br: &BlockReference) -> Value {
     }
 }
 
+// 数值字段上游可能返回字符串或 JSON 数字，两种形式都转为字符串
+fn json_to_string_num(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
 // 直接从JSON值转换为TransactionHistoryEntry
 fn tx_json_to_entry(tx_json: &Value) -> Option<TransactionHistoryEntry> {
     Some(TransactionHistoryEntry {
         tx_hash: tx_json.get("hash")?.as_str().unwrap_or("").to_string(),
-        block_number: tx_json
-            .get("blockNumber")?
-            .as_str()
-            .unwrap_or("0")
-            .to_string(),
+        block_number: json_to_string_num(tx_json.get("blockNumber")?).unwrap_or_else(|| "0".into()),
         blockchain: tx_json
             .get("blockchain")?
             .as_str()
             .unwrap_or("0")
             .to_string(),
-        timestamp: tx_json
-            .get("timestamp")?
-            .as_str()
-            .unwrap_or("0")
-            .to_string(),
+        timestamp: json_to_string_num(tx_json.get("timestamp")?).unwrap_or_else(|| "0".into()),
         from: tx_json.get("from")?.as_str().unwrap_or("").to_string(),
         to: tx_json
             .get("to")
             .and_then(|v| v.as_str())
             .unwrap_or("")
             .to_string(),
-        value: tx_json.get("value")?.as_str().unwrap_or("0").to_string(),
+        value: json_to_string_num(tx_json.get("value")?).unwrap_or_else(|| "0".into()),
         gas_price: tx_json
             .get("gasPrice")
-            .and_then(|v| v.as_str())
-            .unwrap_or("0")
-            .to_string(),
+            .and_then(json_to_string_num)
+            .unwrap_or_else(|| "0".into()),
         gas_used: tx_json
             .get("gasUsed")
-            .and_then(|v| v.as_str())
-            .unwrap_or("0")
-            .to_string(),
+            .and_then(json_to_string_num)
+            .unwrap_or_else(|| "0".into()),
+        // 上游仅在 includeLogs=true 时返回 logs 字段
+        logs: tx_json
+            .get("logs")
+            .and_then(|v| v.as_array())
+            .map(|logs| logs.iter().map(log_json_to_entry).collect())
+            .unwrap_or_default(),
     })
 }
 
+fn log_json_to_entry(log_json: &Value) -> TxLog {
+    let str_field = |key: &str| {
+        log_json
+            .get(key)
+            .and_then(|v| v.as_str())
+            .unwrap_or("")
+            .to_string()
+    };
+    TxLog {
+        address: str_field("address"),
+        topics: log_json
+            .get("topics")
+            .and_then(|v| v.as_array())
+            .map(|topics| {
+                topics
+                    .iter()
+                    .filter_map(|t| t.as_str().map(str::to_string))
+                    .collect()
+            })
+            .unwrap_or_default(),
+        data: str_field("data"),
+        log_index: str_field("logIndex"),
+    }
+}
+
+// 内部错误转换为 gRPC Status：已是 Status 的（如参数校验失败）原样返回，上游 HTTP 错误视为 unavailable，其余视为 internal
+fn to_status(e: AppError) -> Status {
+    match e {
+        AppError::Status(status) => status,
+        // 重试耗尽后仍为 429/5xx 或 key 被拒：对调用方而言上游暂不可用
+        AppError::UpstreamStatus(code) => {
+            Status::unavailable(format!("upstream returned HTTP {}", code))
+        }
+        other => Status::internal(redact(&format!("Error: {}", other))),
+    }
+}
+
+// 索引服务处于维护模式时直接拒绝，并通过 retry-after metadata 提示客户端稍后重试
+fn check_maintenance(state: &AppState) -> std::result::Result<(), Status> {
+    if !state.maintenance.is_enabled("indexer") {
+        return Ok(());
+    }
+    let mut status = Status::unavailable("indexer is under maintenance, retry later");
+    if let Ok(v) = MetadataValue::try_from(state.maintenance.retry_after.as_secs().to_string()) {
+        status.metadata_mut().insert("retry-after", v);
+    }
+    Err(status)
+}
+
+// 未配置 ANKR_API_KEY(S) 时上游必然 401，直接返回明确的 failed_precondition
+fn check_upstream_keys(state: &AppState) -> std::result::Result<(), Status> {
+    if state.ankr_keys.has_keys() {
+        return Ok(());
+    }
+    Err(Status::failed_precondition(
+        "indexer upstream is not configured (ANKR_API_KEY is not set)",
+    ))
+}
+
+// 调用方标识：与限流拦截器一致，mTLS 证书身份优先，否则使用 uuid metadata
+fn request_identity<T>(request: &Request<T>) -> String {
+    if let Some(identity) = extract_client_cert_identity(request) {
+        return format!("cert:{}", identity);
+    }
+    request
+        .metadata()
+        .get("uuid")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_string()
+}
+
+// 请求的链列表（上游链名，逗号分隔），用于访问日志
+fn requested_chains(blockchain: &[i32]) -> String {
+    blockchain
+        .iter()
+        .filter_map(blockchain_to_str)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+// 投递一条访问日志（异步批量写库，不阻塞请求）
+fn record_access<T: Message>(
+    identity: &str,
+    chains: String,
+    method: &'static str,
+    result: &std::result::Result<Response<T>, Status>,
+    elapsed: Duration,
+) {
+    let (status, bytes) = match result {
+        Ok(resp) => (tonic::Code::Ok, resp.get_ref().encoded_len()),
+        Err(status) => (status.code(), 0),
+    };
+    record_request(RequestLogEntry::new(
+        identity,
+        "ankr",
+        chains,
+        method,
+        format!("{:?}", status),
+        elapsed,
+        bytes,
+    ));
+}
+
+// 规范化请求中的全部地址（小写，可选 EIP-55 严格校验）
+fn normalize_addresses(address: &[String], strict: bool) -> std::result::Result<Vec<String>, Status> {
+    address.iter().map(|a| normalize_address(a, strict)).collect()
+}
+
+// 请求参数校验（地址已由 normalize_addresses 校验格式）：address 不能为空，blockchain 过滤掉 UNDEFINED 后至少保留一条
+fn validate_indexer_request(
+    address: &[String],
+    blockchain_names: &[String],
+) -> std::result::Result<(), Status> {
+    if address.first().is_none_or(|a| a.trim().is_empty()) {
+        return Err(Status::invalid_argument("address must contain at least one address"));
+    }
+    if blockchain_names.is_empty() {
+        return Err(Status::invalid_argument(
+            "blockchain must contain at least one supported chain",
+        ));
+    }
+    Ok(())
+}
+
 #[tonic::async_trait]
 impl AnkrIndexer for IndexService {
+    type StreamTransactionHistoryStream = ReceiverStream<std::result::Result<TxHistoryList, Status>>;
+    type StreamAssetBalanceStream = ReceiverStream<std::result::Result<HotAssetList, Status>>;
+
     async fn get_transaction_history(
         &self,
         request: Request<AnkrTxHisRequest>,
     ) -> std::result::Result<Response<TxHistoryList>, Status> {
-        match self.get_transaction_history_internal(request.into_inner()).await {
-            Ok(response) => Ok(response),
-            Err(e) => Err(Status::internal(format!("Error: {}", e))),
-        }
+        let request_id = request_id_from(&request);
+        let identity = request_identity(&request);
+        let mut req = request.into_inner();
+        let strict = self.state.indexer.strict_checksum;
+        let chains = requested_chains(&req.blockchain);
+        let start = Instant::now();
+
+        let sampler = self.state.log_sampler.clone();
+        let result = with_request_id("get_transaction_history", request_id, &sampler, async move {
+            check_maintenance(&self.state)?;
+            check_upstream_keys(&self.state)?;
+            // 先规范化地址，保证大小写不同的同一地址命中同一缓存
+            req.address = normalize_addresses(&req.address, strict)?;
+            let mut key_req = req.clone();
+            key_req.uuid.clear();
+            key_req.no_cache = false;
+
+            with_cache(
+                &self.state.tx_cache,
+                format!("tx:{}", request_cache_key(&key_req)),
+                req.no_cache,
+                |list| list.failed_chains.is_empty(),
+                self.get_transaction_history_internal(req),
+            )
+            .await
+            .map_err(to_status)
+        })
+        .await;
+        record_access(&identity, chains, "get_transaction_history", &result, start.elapsed());
+        result
     }
 
     async fn get_asset_balance(
         &self,
         request: Request<AnkrAssetRequest>,
     ) -> std::result::Result<Response<HotAssetList>, Status> {
+        let request_id = request_id_from(&request);
+        let identity = request_identity(&request);
+        let mut req = request.into_inner();
+        let strict = self.state.indexer.strict_checksum;
+        let chains = requested_chains(&req.blockchain);
+        let start = Instant::now();
 
-        match self.get_asset_balance_internal(request.into_inner()).await {
-            Ok(response) => Ok(response),
-            Err(e) => Err(Status::internal(format!("Error: {}", e))),
-        }
+        let sampler = self.state.log_sampler.clone();
+        let result = with_request_id("get_asset_balance", request_id, &sampler, async move {
+            check_maintenance(&self.state)?;
+            check_upstream_keys(&self.state)?;
+            // 先规范化地址，保证大小写不同的同一地址命中同一缓存
+            req.address = normalize_addresses(&req.address, strict)?;
+            let mut key_req = req.clone();
+            key_req.uuid.clear();
+            key_req.no_cache = false;
+
+            with_cache(
+                &self.state.asset_cache,
+                format!("asset:{}", request_cache_key(&key_req)),
+                req.no_cache,
+                |list| list.failed_chains.is_empty(),
+                self.get_asset_balance_internal(req),
+            )
+            .await
+            .map_err(to_status)
+        })
+        .await;
+        record_access(&identity, chains, "get_asset_balance", &result, start.elapsed());
+        result
     }
-}
 
-impl IndexService {
-    async fn get_transaction_history_internal(
+    async fn stream_transaction_history(
         &self,
-        req: AnkrTxHisRequest,
-    ) -> Result<Response<TxHistoryList>> {
-        let mut all_entries = Vec::new();
+        request: Request<AnkrTxHisRequest>,
+    ) -> std::result::Result<Response<Self::StreamTransactionHistoryStream>, Status> {
+        let request_id = request_id_from(&request);
+        let identity = request_identity(&request);
+        let mut req = request.into_inner();
+        let strict = self.state.indexer.strict_checksum;
+        let chains = requested_chains(&req.blockchain);
+        let start = Instant::now();
 
-        // 初始 page_token：如果客户端传 "" 或根本没传，就视为第一页
-        let mut current_page_token: Option<String> = if req.page_token.is_empty() {
-            None
-        } else {
-            Some(req.page_token)
-        };
+        let sampler = self.state.log_sampler.clone();
+        with_request_id("stream_transaction_history", request_id, &sampler, async move {
+            check_maintenance(&self.state)?;
+            check_upstream_keys(&self.state)?;
+            req.address = normalize_addresses(&req.address, strict)?;
+            let blockchain_names = self.validate_tx_history_request(&req).map_err(to_status)?;
+            let body = tx_history_body(&req, blockchain_names);
+
+            let (tx, rx) = mpsc::channel(STREAM_BUFFER_PAGES);
+            let state = self.state.clone();
+            let request_id = REQUEST_ID.with(String::clone);
+            // handler 返回后流仍在推送，由后台任务持有计数，关闭时等待其结束
+            let in_flight = state.in_flight.guard();
+            // 后台任务沿用当前请求的 span 与 request_id，上游请求仍带 X-Request-ID
+            let task = async move {
+                let _in_flight = in_flight;
+                let (code, bytes) = stream_pages(
+                    &state.client,
+                    &state.ankr_keys,
+                    body,
+                    TX_PAGE_SIZE,
+                    state.indexer.tx_history_cap,
+                    "tx_history",
+                    extract_txs,
+                    // 去重只在页内进行，跨页重复由客户端处理
+                    |txs, next_page_token| TxHistoryList {
+                        txs: dedup_txs(txs),
+                        next_page_token,
+                        failed_chains: Vec::new(),
+                    },
+                    &tx,
+                )
+                .await;
+                record_request(RequestLogEntry::new(
+                    &identity,
+                    "ankr",
+                    chains,
+                    "stream_transaction_history",
+                    format!("{:?}", code),
+                    start.elapsed(),
+                    bytes,
+                ));
+            };
+            tokio::spawn(REQUEST_ID.scope(request_id, task).instrument(Span::current()));
+
+            Ok(Response::new(ReceiverStream::new(rx)))
+        })
+        .await
+    }
+
+    async fn stream_asset_balance(
+        &self,
+        request: Request<AnkrAssetRequest>,
+    ) -> std::result::Result<Response<Self::StreamAssetBalanceStream>, Status> {
+        let request_id = request_id_from(&request);
+        let identity = request_identity(&request);
+        let mut req = request.into_inner();
+        let strict = self.state.indexer.strict_checksum;
+        let chains = requested_chains(&req.blockchain);
+        let start = Instant::now();
 
-        loop {
-            // 过滤掉None值并收集有效的区块链名称
+        let sampler = self.state.log_sampler.clone();
+        with_request_id("stream_asset_balance", request_id, &sampler, async move {
+            check_maintenance(&self.state)?;
+            check_upstream_keys(&self.state)?;
+            req.address = normalize_addresses(&req.address, strict)?;
             let blockchain_names: Vec<String> = req
                 .blockchain
                 .iter()
-                .filter_map(|&b| blockchain_to_str(&b))
+                .filter_map(blockchain_to_str)
                 .collect();
-
-            let mut body = serde_json::json!({
-                "blockchain": blockchain_names,
-                "address": &req.address[0],
-                "decodeTxData": true,
-                "includeLogs": false,
-                "descOrder": true,
-                "pageSize": 100,
+            validate_indexer_request(&req.address, &blockchain_names)?;
+            let owner = req.address[0].clone();
+            let page_token = AssetPageToken::decode(&req.page_token)?;
+            // 续页时只拉取 token 中仍有下一页的一路
+            let first_page = req.page_token.is_empty();
+            let balances_body = (first_page || !page_token.balances.is_empty()).then(|| {
+                balances_request_body(&req, &page_token.balances, blockchain_names.clone())
             });
+            let nfts_body = (first_page || !page_token.nfts.is_empty())
+                .then(|| asset_request_body(&req, &page_token.nfts, blockchain_names));
 
-            // 只有当 current_page_token 是 Some(非空) 时才加 pageToken 字段
-            if let Some(ref token) = current_page_token {
-                body["pageToken"] = serde_json::Value::String(token.clone());
-            }
+            let (tx, rx) = mpsc::channel(STREAM_BUFFER_PAGES);
+            let state = self.state.clone();
+            let request_id = REQUEST_ID.with(String::clone);
+            let in_flight = state.in_flight.guard();
+            // 余额与 NFT 两路并发翻页，共用同一个 channel，按页完成顺序交错推送
+            let task = async move {
+                let _in_flight = in_flight;
+                let cap = state.indexer.asset_cap;
+                let to_message = |assets, next_page_token: AssetPageToken| HotAssetList {
+                    assets: dedup_assets(assets),
+                    failed_chains: Vec::new(),
+                    next_page_token: next_page_token.encode(),
+                };
+                let balances = async {
+                    let Some(body) = balances_body else {
+                        return (tonic::Code::Ok, 0);
+                    };
+                    stream_pages(
+                        &state.client,
+                        &state.ankr_keys,
+                        body,
+                        ASSET_PAGE_SIZE,
+                        cap,
+                        "balances",
+                        |resp| extract_balances(&owner, resp),
+                        |assets, balances| to_message(assets, AssetPageToken { balances, ..Default::default() }),
+                        &tx,
+                    )
+                    .await
+                };
+                let nfts = async {
+                    let Some(body) = nfts_body else {
+                        return (tonic::Code::Ok, 0);
+                    };
+                    stream_pages(
+                        &state.client,
+                        &state.ankr_keys,
+                        body,
+                        ASSET_PAGE_SIZE,
+                        cap,
+                        "nfts",
+                        |resp| extract_nfts(&owner, resp),
+                        |assets, nfts| to_message(assets, AssetPageToken { nfts, ..Default::default() }),
+                        &tx,
+                    )
+                    .await
+                };
+                let ((balances_code, balances_bytes), (nfts_code, nfts_bytes)) = tokio::join!(balances, nfts);
+                let code = if balances_code != tonic::Code::Ok {
+                    balances_code
+                } else {
+                    nfts_code
+                };
+                record_request(RequestLogEntry::new(
+                    &identity,
+                    "ankr",
+                    chains,
+                    "stream_asset_balance",
+                    format!("{:?}", code),
+                    start.elapsed(),
+                    balances_bytes + nfts_bytes,
+                ));
+            };
+            tokio::spawn(REQUEST_ID.scope(request_id, task).instrument(Span::current()));
 
-            if let Some(ref from) = req.from_timestamp {
-                body["fromTimestamp"] = block_ref_to_json(from);
-            }
-            if let Some(ref to) = req.to_timestamp {
-                body["toTimestamp"] = block_ref_to_json(to);
-            }
+            Ok(Response::new(ReceiverStream::new(rx)))
+        })
+        .await
+    }
+}
 
-            let endpoint = format!("https://rpc.ankr.com/multichain/{}", self.state.ankr_key);
-
-            // 直接获取JSON响应，而不反序列化为结构体
-            let ankr_resp: Value = self
-                .state
-                .client
-                .post(&endpoint)
-                .json(&body)
-                .send()
-                .await
-                .map_err(AppError::from)?
-                .json()
-                .await
-                .map_err(AppError::from)?;
-
-            // 直接从JSON中提取交易数据
-            if let Some(transactions) = ankr_resp.get("transactions").and_then(|t| t.as_array()) {
-                let page_entries = transactions
-                    .iter()
-                    .filter_map(|tx_json| tx_json_to_entry(tx_json))
-                    .collect::<Vec<_>>();
+tokio::task_local! {
+    // 当前请求的关联 ID：随上游请求转发，并写回响应 metadata
+    static REQUEST_ID: String;
+}
 
-                all_entries.extend(page_entries);
-            }
+// 读取客户端传入的 x-request-id，缺失或不合法时生成新的 UUID
+fn request_id_from<T>(request: &Request<T>) -> String {
+    request
+        .metadata()
+        .get("x-request-id")
+        .and_then(|v| v.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= 128)
+        .map(str::to_string)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string())
+}
 
-            // 判断是否有下一页
-            let next_page_token = ankr_resp
-                .get("nextPageToken")
-                .and_then(|t| t.as_str())
-                .unwrap_or("");
-
-            if !next_page_token.is_empty() {
-                current_page_token = Some(next_page_token.to_string());
-            } else {
-                // 没有下一页，退出循环
-                current_page_token = None;
-                break;
-            }
+// 在带 request_id 字段的 span 中执行请求，并把 ID 回写到响应（成功或失败）
+// 请求完成日志按 LogSampler 采样，失败请求总是记录
+async fn with_request_id<T, Fut>(
+    method: &'static str,
+    request_id: String,
+    sampler: &LogSampler,
+    fut: Fut,
+) -> std::result::Result<Response<T>, Status>
+where
+    Fut: Future<Output = std::result::Result<Response<T>, Status>>,
+{
+    let span = info_span!("indexer", method, request_id = %request_id);
+    let header = MetadataValue::try_from(request_id.as_str()).ok();
+    let start = Instant::now();
+    let result = REQUEST_ID.scope(request_id, fut).instrument(span.clone()).await;
+
+    let code = result.as_ref().err().map_or(tonic::Code::Ok, Status::code);
+    if sampler.should_log(method, code != tonic::Code::Ok) {
+        span.in_scope(|| {
+            info!(
+                code = ?code,
+                elapsed_ms = start.elapsed().as_millis() as u64,
+                "indexer request completed"
+            )
+        });
+    }
+
+    let Some(header) = header else { return result };
+    match result {
+        Ok(mut resp) => {
+            resp.metadata_mut().insert("x-request-id", header);
+            Ok(resp)
+        }
+        Err(mut status) => {
+            status.metadata_mut().insert("x-request-id", header);
+            Err(status)
+        }
+    }
+}
+
+// 缓存 key：去掉 uuid / no_cache 后的请求编码的 sha256，不同客户端的相同查询共享缓存
+fn request_cache_key(req: &impl Message) -> String {
+    hex::encode(Sha256::digest(req.encode_to_vec()))
+}
+
+// 命中缓存直接返回；否则回源并缓存结果。no_cache 只跳过读取，刷新后的结果仍写回缓存。
+// 相同 key 的并发请求经 moka try_get_with 合并为一次回源（single-flight），其余请求等待同一结果。
+// 部分链失败或带 PartialResult 标记（翻页中途失败）的结果不缓存，避免在 TTL 内持续返回残缺数据。
+async fn with_cache<T, Fut>(
+    cache: &ResponseCache<T>,
+    key: String,
+    no_cache: bool,
+    cacheable: impl Fn(&T) -> bool,
+    fetch: Fut,
+) -> Result<Response<T>>
+where
+    T: Clone + Send + Sync + 'static,
+    Fut: Future<Output = Result<Response<T>>>,
+{
+    if no_cache {
+        let response = fetch.await?;
+        if response.extensions().get::<PartialResult>().is_none() && cacheable(response.get_ref()) {
+            let entry = (response.get_ref().clone(), response.metadata().clone());
+            cache.insert(key, Arc::new(entry)).await;
+        }
+        return Ok(response);
+    }
+
+    let mut fetched = false;
+    let mut partial = false;
+    let entry = cache
+        .try_get_with(key.clone(), async {
+            fetched = true;
+            let response = fetch.await.map_err(to_status)?;
+            partial = response.extensions().get::<PartialResult>().is_some();
+            let (metadata, message, _) = response.into_parts();
+            Ok::<_, Status>(Arc::new((message, metadata)))
+        })
+        .await
+        .map_err(|e| AppError::Status(e.as_ref().clone()))?;
+
+    if !fetched {
+        debug!(key = %key, "indexer cache hit");
+    }
+    if partial || !cacheable(&entry.0) {
+        cache.invalidate(&key).await;
+    }
+    let (message, metadata) = entry.as_ref().clone();
+    Ok(Response::from_parts(metadata, message, Default::default()))
+}
 
-            if all_entries.len() >= 10_000 {
-                break;
+impl IndexService {
+    // 交易历史请求的公共校验，返回有效的上游链名
+    fn validate_tx_history_request(&self, req: &AnkrTxHisRequest) -> Result<Vec<String>> {
+        // 过滤掉None值并收集有效的区块链名称
+        let blockchain_names: Vec<String> = req
+            .blockchain
+            .iter()
+            .filter_map(|&b| blockchain_to_str(&b))
+            .collect();
+        validate_indexer_request(&req.address, &blockchain_names)?;
+        if req.address.len() > self.state.indexer.max_addresses {
+            return Err(Status::invalid_argument(format!(
+                "at most {} addresses are allowed per request",
+                self.state.indexer.max_addresses
+            ))
+            .into());
+        }
+
+        // 区块范围与时间范围只能二选一
+        let has_block_range = req.from_block.is_some() || req.to_block.is_some();
+        let has_time_range = req.from_timestamp.is_some() || req.to_timestamp.is_some();
+        if has_block_range && has_time_range {
+            return Err(Status::invalid_argument(
+                "specify either a block range or a timestamp range, not both",
+            )
+            .into());
+        }
+
+        let refs = [
+            ("from_block", &req.from_block, false),
+            ("to_block", &req.to_block, false),
+            ("from_timestamp", &req.from_timestamp, true),
+            ("to_timestamp", &req.to_timestamp, true),
+        ];
+        for (field, br, is_timestamp) in refs {
+            if let Some(br) = br {
+                validate_block_ref(field, br, is_timestamp)?;
             }
         }
+        validate_range_order(
+            "from_block",
+            req.from_block.as_ref(),
+            "to_block",
+            req.to_block.as_ref(),
+        )?;
+        validate_range_order(
+            "from_timestamp",
+            req.from_timestamp.as_ref(),
+            "to_timestamp",
+            req.to_timestamp.as_ref(),
+        )?;
+        Ok(blockchain_names)
+    }
 
-        // 返回给客户端的 next_page_token：如果有更多数据，返回下一页的 token，否则返回空字符串
-        let response_next_token = if current_page_token.is_some() {
-            current_page_token.unwrap_or_default() // 返回实际的下一页 token
-        } else {
-            "".to_string()
-        };
+    async fn get_transaction_history_internal(
+        &self,
+        req: AnkrTxHisRequest,
+    ) -> Result<Response<TxHistoryList>> {
+        let blockchain_names = self.validate_tx_history_request(&req)?;
 
-        Ok(Response::new(TxHistoryList {
-            txs: all_entries,
-            next_page_token: response_next_token,
-        }))
+        let (fetched, failed_chains) =
+            fetch_with_chain_fallback(&blockchain_names, req.page_token.is_empty(), |chains| {
+                self.fetch_tx_history(&req, chains)
+            })
+            .await?;
+
+        let mut response = Response::new(TxHistoryList {
+            txs: dedup_txs(fetched.entries),
+            next_page_token: fetched.next_page_token,
+            failed_chains,
+        });
+        attach_sync_status(&mut response, fetched.sync_status.as_ref());
+        if fetched.partial {
+            response.extensions_mut().insert(PartialResult);
+        }
+        Ok(response)
+    }
+
+    async fn fetch_tx_history(
+        &self,
+        req: &AnkrTxHisRequest,
+        blockchain_names: Vec<String>,
+    ) -> Result<Fetched<TransactionHistoryEntry>> {
+        fetch_all_pages(
+            &self.state.client,
+            &self.state.ankr_keys,
+            tx_history_body(req, blockchain_names),
+            TX_PAGE_SIZE,
+            self.state.indexer.tx_history_cap,
+            "tx_history",
+            extract_txs,
+        )
+        .await
     }
 
     async fn get_asset_balance_internal(
         &self,
         req: AnkrAssetRequest,
     ) -> Result<Response<HotAssetList>> {
-        let endpoint = format!("https://rpc.ankr.com/multichain/{}", self.state.ankr_key);
+        let keys = &self.state.ankr_keys;
+        let client = &self.state.client;
+        let cap = self.state.indexer.asset_cap;
+        let first_page = req.page_token.is_empty();
+        let page_token = AssetPageToken::decode(&req.page_token)?;
 
-        // 获取余额数据
-        let balance_entries = get_balances_by_owner(&self.state.client, &req, &endpoint).await?;
+        // 过滤掉None值并收集有效的区块链名称
+        let blockchain_names: Vec<String> = req
+            .blockchain
+            .iter()
+            .filter_map(|&b| blockchain_to_str(&b))
+            .collect();
+        validate_indexer_request(&req.address, &blockchain_names)?;
+
+        // 获取余额数据；续页时 token 中已拉完的一路直接跳过
+        let (balances, mut failed_chains) = if first_page || !page_token.balances.is_empty() {
+            fetch_with_chain_fallback(&blockchain_names, first_page, |chains| {
+                get_balances_by_owner(client, &req, &page_token.balances, keys, chains, cap)
+            })
+            .await?
+        } else {
+            (Fetched::default(), Vec::new())
+        };
 
         // 获取 NFT 数据
-        let nft_entries = get_nft_by_owner(&self.state.client, &req, &endpoint).await?;
+        let (nfts, nft_failed) = if first_page || !page_token.nfts.is_empty() {
+            fetch_with_chain_fallback(&blockchain_names, first_page, |chains| {
+                get_nft_by_owner(client, &req, &page_token.nfts, keys, chains, cap)
+            })
+            .await?
+        } else {
+            (Fetched::default(), Vec::new())
+        };
+
+        for chain in nft_failed {
+            if !failed_chains.contains(&chain) {
+                failed_chains.push(chain);
+            }
+        }
 
-        let mut all_entries = balance_entries;
-        all_entries.extend(nft_entries);
+        let mut all_entries = balances.entries;
+        all_entries.extend(nfts.entries);
+        let all_entries = dedup_assets(all_entries);
 
-        Ok(Response::new(HotAssetList {
+        // 任一路达到 asset_cap 被截断时返回组合 token，客户端可从截断处继续
+        let next_page_token = AssetPageToken {
+            balances: balances.next_page_token,
+            nfts: nfts.next_page_token,
+        }
+        .encode();
+        let mut response = Response::new(HotAssetList {
             assets: all_entries,
-        }))
+            failed_chains,
+            next_page_token,
+        });
+        if balances.partial || nfts.partial {
+            response.extensions_mut().insert(PartialResult);
+        }
+        attach_sync_status(&mut response, balances.sync_status.or(nfts.sync_status).as_ref());
+        Ok(response)
+    }
+}
+
+// 多地址查询时同一笔交易可能同时命中多个地址，按 (blockchain, tx_hash) 去重
+fn dedup_txs(txs: Vec<TransactionHistoryEntry>) -> Vec<TransactionHistoryEntry> {
+    let mut seen = HashSet::with_capacity(txs.len());
+    txs.into_iter()
+        .filter(|tx| seen.insert((tx.blockchain.clone(), tx.tx_hash.clone())))
+        .collect()
+}
+
+// 按 (blockchain, contract_address, token_id) 去重，保留首次出现的顺序
+// 防止上游分页重叠导致同一资产重复计入
+fn dedup_assets(assets: Vec<HotAsset>) -> Vec<HotAsset> {
+    let total = assets.len();
+    let mut seen = HashSet::with_capacity(total);
+    let deduped: Vec<HotAsset> = assets
+        .into_iter()
+        .filter(|a| {
+            seen.insert((
+                a.blockchain.clone(),
+                a.contract_address.clone(),
+                a.token_id.clone(),
+            ))
+        })
+        .collect();
+
+    if deduped.len() < total {
+        debug!(dropped = total - deduped.len(), "dropped duplicate assets");
+    }
+    deduped
+}
+
+// 一次（可能跨多页的）上游拉取结果
+struct Fetched<T> {
+    entries: Vec<T>,
+    next_page_token: String,
+    sync_status: Option<SyncStatus>,
+    // 中途某页重试耗尽，只返回了已拉取的部分
+    partial: bool,
+}
+
+impl<T> Default for Fetched<T> {
+    fn default() -> Self {
+        Self {
+            entries: Vec::new(),
+            next_page_token: String::new(),
+            sync_status: None,
+            partial: false,
+        }
+    }
+}
+
+// 部分结果的响应标记（放在 Response extensions 中），with_cache 据此跳过缓存
+#[derive(Clone, Copy, Debug)]
+struct PartialResult;
+
+// 先按多链整体请求；若失败且为多链首页请求，则按链拆分并发重试，
+// 成功链的结果合并返回，失败链记入 failed_chains。
+// 多链 page_token 无法拆分到单链，因此拆分后不返回 next_page_token。
+async fn fetch_with_chain_fallback<T, F, Fut>(
+    chains: &[String],
+    first_page: bool,
+    fetch: F,
+) -> Result<(Fetched<T>, Vec<String>)>
+where
+    F: Fn(Vec<String>) -> Fut,
+    Fut: Future<Output = Result<Fetched<T>>>,
+{
+    let err = match fetch(chains.to_vec()).await {
+        Ok(fetched) => return Ok((fetched, Vec::new())),
+        Err(e) if chains.len() > 1 && first_page => e,
+        Err(e) => return Err(e),
+    };
+    warn!(error = %redact(&err.to_string()), "multichain request failed, retrying per chain");
+
+    let results = join_all(chains.iter().map(|chain| fetch(vec![chain.clone()]))).await;
+
+    let mut merged = Fetched::default();
+    let mut failed_chains = Vec::new();
+    for (chain, result) in chains.iter().zip(results) {
+        match result {
+            Ok(fetched) => {
+                merged.entries.extend(fetched.entries);
+                merged.sync_status = merged.sync_status.or(fetched.sync_status);
+                merged.partial |= fetched.partial;
+            }
+            Err(e) => {
+                warn!(chain = %chain, error = %redact(&e.to_string()), "chain request failed");
+                failed_chains.push(chain.clone());
+            }
+        }
+    }
+
+    // 所有链都失败时返回原始错误
+    if failed_chains.len() == chains.len() {
+        return Err(err);
     }
+    Ok((merged, failed_chains))
 }
 
 // 直接从JSON值转换为HotAsset (余额)
@@ -231,11 +1218,8 @@ fn balance_json_to_asset(address: &str, balance_json: &Value) -> Option<HotAsset
             .unwrap_or("")
             .to_string(),
         symbol: balance_json.get("tokenSymbol")?.as_str()?.to_string(),
-        decimals: balance_json
-            .get("tokenDecimals")?
-            .as_u64()
-            .unwrap_or(0)
-            .to_string(),
+        decimals: json_to_string_num(balance_json.get("tokenDecimals")?)
+            .unwrap_or_else(|| "0".into()),
         token_id: "".to_string(),
         thumbnail: balance_json
             .get("thumbnail")?
@@ -253,16 +1237,8 @@ fn balance_json_to_asset(address: &str, balance_json: &Value) -> Option<HotAsset
             .and_then(|v| v.as_str())
             .unwrap_or("")
             .to_string(),
-        balance: balance_json
-            .get("balanceUsd")?
-            .as_str()
-            .unwrap_or("0")
-            .to_string(),
-        price: balance_json
-            .get("tokenPrice")?
-            .as_str()
-            .unwrap_or("0")
-            .to_string(),
+        balance: json_to_string_num(balance_json.get("balanceUsd")?).unwrap_or_else(|| "0".into()),
+        price: json_to_string_num(balance_json.get("tokenPrice")?).unwrap_or_else(|| "0".into()),
     })
 }
 
@@ -278,7 +1254,7 @@ fn nft_json_to_asset(address: &str, nft_json: &Value) -> Option<HotAsset> {
         name: nft_json.get("name")?.as_str().unwrap_or("").to_string(),
         symbol: nft_json.get("symbol")?.as_str().unwrap_or("").to_string(),
         decimals: "".to_string(),
-        token_id: nft_json.get("tokenId")?.as_str().unwrap_or("0").to_string(),
+        token_id: json_to_string_num(nft_json.get("tokenId")?).unwrap_or_else(|| "0".into()),
         thumbnail: nft_json
             .get("imageUrl")
             .and_then(|v| v.as_str())
@@ -301,158 +1277,963 @@ fn nft_json_to_asset(address: &str, nft_json: &Value) -> Option<HotAsset> {
             .to_string(),
         balance: nft_json
             .get("quantity")
-            .and_then(|v| v.as_str())
-            .unwrap_or("0")
-            .to_string(),
+            .and_then(json_to_string_num)
+            .unwrap_or_else(|| "0".into()),
         price: "".to_string(),
     })
 }
 
-async fn get_balances_by_owner(
+// 通用分页拉取：在 base_body 上补 pageSize/pageToken，循环直到没有下一页或达到 cap。
+// extract 从单页响应中取出条目；返回的 next_page_token 可供客户端继续翻页。
+async fn fetch_all_pages<T>(
     client: &reqwest::Client,
-    request: &AnkrAssetRequest,
-    endpoint: &str,
-) -> Result<Vec<HotAsset>> {
-    let mut all_entries = Vec::new();
-
-    // 初始 page_token：如果客户端传 "" 或根本没传，就视为第一页
-    let mut current_page_token: Option<String> = if request.page_token.is_empty() {
-        None
-    } else {
-        Some(request.page_token.clone())
+    keys: &ApiKeyPool,
+    base_body: Value,
+    page_size: usize,
+    cap: usize,
+    op: &'static str,
+    extract: impl Fn(&Value) -> Vec<T>,
+) -> Result<Fetched<T>> {
+    let mut body = base_body;
+    let mut entries = Vec::new();
+    let mut sync_status = None;
+    let mut partial = false;
+
+    let next_page_token = loop {
+        // 接近上限时缩小页大小，保证截断处与返回的 page token 对齐
+        body["pageSize"] = page_size.min(cap.saturating_sub(entries.len())).into();
+
+        // 直接获取JSON响应，而不反序列化为结构体
+        let resp = match post_upstream_with_retry(client, keys, &body, op).await {
+            Ok(resp) => resp,
+            // 重试耗尽时不丢弃已拉取的页：返回已收集条目，并以失败页的 token 作为续页 token
+            Err(e) if !entries.is_empty() => {
+                warn!(
+                    op,
+                    collected = entries.len(),
+                    error = %redact(&e.to_string()),
+                    "page fetch failed, returning partial results"
+                );
+                partial = true;
+                break body["pageToken"].as_str().unwrap_or_default().to_string();
+            }
+            Err(e) => return Err(e),
+        };
+        sync_status = parse_sync_status(&resp).or(sync_status);
+        entries.extend(extract(&resp));
+
+        // 判断是否有下一页
+        let token = resp
+            .get("nextPageToken")
+            .and_then(|t| t.as_str())
+            .unwrap_or("");
+        if token.is_empty() || entries.len() >= cap {
+            break token.to_string();
+        }
+        body["pageToken"] = Value::String(token.to_string());
     };
 
+    Ok(Fetched {
+        entries,
+        next_page_token,
+        sync_status,
+        partial,
+    })
+}
+
+// 流式分页：每拉到一页就通过 channel 推送给客户端，不在内存中累积。
+// channel 容量即背压（客户端读取慢时暂停翻页）；客户端断开时停止拉取。
+// 返回最终状态码与已推送的字节数，用于访问日志。
+#[allow(clippy::too_many_arguments)]
+async fn stream_pages<T, M: Message>(
+    client: &reqwest::Client,
+    keys: &ApiKeyPool,
+    base_body: Value,
+    page_size: usize,
+    cap: usize,
+    op: &'static str,
+    extract: impl Fn(&Value) -> Vec<T>,
+    to_message: impl Fn(Vec<T>, String) -> M,
+    tx: &mpsc::Sender<std::result::Result<M, Status>>,
+) -> (tonic::Code, usize) {
+    let mut body = base_body;
+    let mut sent = 0;
+    let mut bytes = 0;
     loop {
-        // 过滤掉None值并收集有效的区块链名称
-        let blockchain_names: Vec<String> = request
-            .blockchain
-            .iter()
-            .filter_map(|&b| blockchain_to_str(&b))
-            .collect();
+        body["pageSize"] = page_size.min(cap.saturating_sub(sent)).into();
 
-        let mut body = serde_json::json!({
-            "blockchain": blockchain_names,
-            "address": &request.address[0],
-            "onlyWhitelisted": &request.only_whitelisted,
-            "pageSize": 50,
-        });
+        // 客户端断开时立即放弃进行中的上游请求
+        let fetched = tokio::select! {
+            resp = post_upstream_with_retry(client, keys, &body, op) => resp,
+            _ = tx.closed() => {
+                debug!(op, sent, "client closed stream, stopping pagination");
+                return (tonic::Code::Cancelled, bytes);
+            }
+        };
+        let resp = match fetched {
+            Ok(resp) => resp,
+            // 已推送页的 next_page_token 即为失败页的 token，客户端可据此续拉
+            Err(e) => {
+                let status = to_status(e);
+                let code = status.code();
+                let _ = tx.send(Err(status)).await;
+                return (code, bytes);
+            }
+        };
+        let entries = extract(&resp);
+        sent += entries.len();
+        let token = resp
+            .get("nextPageToken")
+            .and_then(|t| t.as_str())
+            .unwrap_or("")
+            .to_string();
+        let done = token.is_empty() || sent >= cap;
 
-        // 只有当 current_page_token 是 Some(非空) 时才加 pageToken 字段
-        if let Some(ref token) = current_page_token {
-            body["pageToken"] = serde_json::Value::String(token.clone());
+        let message = to_message(entries, token.clone());
+        bytes += message.encoded_len();
+        if tx.send(Ok(message)).await.is_err() {
+            debug!(op, sent, "client closed stream, stopping pagination");
+            return (tonic::Code::Cancelled, bytes);
+        }
+        if done {
+            return (tonic::Code::Ok, bytes);
         }
+        body["pageToken"] = Value::String(token);
+    }
+}
 
-        // 直接获取JSON响应，而不反序列化为结构体
-        let balance_resp: Value = client
-            .post(endpoint)
-            .json(&body)
-            .send()
-            .await
-            .map_err(AppError::from)?
-            .json()
-            .await
-            .map_err(AppError::from)?;
+// 交易历史请求 body（Ankr 接受地址数组，多地址的结果由上游统一分页）
+fn tx_history_body(req: &AnkrTxHisRequest, blockchain_names: Vec<String>) -> Value {
+    let mut body = serde_json::json!({
+        "blockchain": blockchain_names,
+        "address": &req.address,
+        "decodeTxData": true,
+        "includeLogs": req.include_logs.unwrap_or(false),
+        "descOrder": req.desc_order.unwrap_or(true),
+    });
 
-        // 直接从JSON中提取余额数据
-        if let Some(assets) = balance_resp.get("assets").and_then(|t| t.as_array()) {
-            let page_entries = assets
-                .iter()
-                .filter_map(|balance_json| balance_json_to_asset(&request.address[0], balance_json))
-                .collect::<Vec<_>>();
+    // 只有当客户端传了非空 page_token 时才加 pageToken 字段
+    if !req.page_token.is_empty() {
+        body["pageToken"] = Value::String(req.page_token.clone());
+    }
 
-            all_entries.extend(page_entries);
-        }
+    if let Some(ref from) = req.from_timestamp {
+        body["fromTimestamp"] = block_ref_to_json(from);
+    }
+    if let Some(ref to) = req.to_timestamp {
+        body["toTimestamp"] = block_ref_to_json(to);
+    }
+    if let Some(ref from) = req.from_block {
+        body["fromBlock"] = block_ref_to_json(from);
+    }
+    if let Some(ref to) = req.to_block {
+        body["toBlock"] = block_ref_to_json(to);
+    }
+    body
+}
 
-        // 判断是否有下一页
-        let next_page_token = balance_resp
-            .get("nextPageToken")
-            .and_then(|t| t.as_str())
-            .unwrap_or("");
+fn extract_txs(resp: &Value) -> Vec<TransactionHistoryEntry> {
+    resp.get("transactions")
+        .and_then(|t| t.as_array())
+        .map(|txs| txs.iter().filter_map(tx_json_to_entry).collect())
+        .unwrap_or_default()
+}
 
-        if !next_page_token.is_empty() {
-            current_page_token = Some(next_page_token.to_string());
-        } else {
-            break;
+// 资产查询的续页 token：余额与 NFT 分别翻页，返回给客户端的是两路上游 token 的组合
+// 编码为 JSON 的 URL-safe base64；已拉完的一路为空串，续页时不再请求
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+struct AssetPageToken {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    balances: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    nfts: String,
+}
+
+impl AssetPageToken {
+    // 空串表示首页
+    fn decode(token: &str) -> std::result::Result<Self, Status> {
+        if token.is_empty() {
+            return Ok(Self::default());
         }
+        BASE64_URL
+            .decode(token)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .ok_or_else(|| Status::invalid_argument("invalid page_token"))
+    }
 
-        if all_entries.len() >= 1000 {
-            break;
+    // 两路都已拉完时返回空串
+    fn encode(&self) -> String {
+        if self.balances.is_empty() && self.nfts.is_empty() {
+            return String::new();
         }
+        BASE64_URL.encode(serde_json::to_vec(self).unwrap_or_default())
     }
+}
 
-    Ok(all_entries)
+// 资产类请求的公共 body：page_token 为该路的上游 token，非空时才加 pageToken 字段
+fn asset_request_body(request: &AnkrAssetRequest, page_token: &str, blockchain_names: Vec<String>) -> Value {
+    let mut body = serde_json::json!({
+        "blockchain": blockchain_names,
+        "address": &request.address[0],
+    });
+    if !page_token.is_empty() {
+        body["pageToken"] = Value::String(page_token.to_string());
+    }
+    body
+}
+
+async fn get_balances_by_owner(
+    client: &reqwest::Client,
+    request: &AnkrAssetRequest,
+    page_token: &str,
+    keys: &ApiKeyPool,
+    blockchain_names: Vec<String>,
+    cap: usize,
+) -> Result<Fetched<HotAsset>> {
+    let body = balances_request_body(request, page_token, blockchain_names);
+    fetch_all_pages(client, keys, body, ASSET_PAGE_SIZE, cap, "balances", |resp| {
+        extract_balances(&request.address[0], resp)
+    })
+    .await
 }
 
 async fn get_nft_by_owner(
     client: &reqwest::Client,
     request: &AnkrAssetRequest,
-    endpoint: &str,
-) -> Result<Vec<HotAsset>> {
-    let mut all_entries = Vec::new();
-
-    // 初始 page_token：如果客户端传 "" 或根本没传，就视为第一页
-    let mut current_page_token: Option<String> = if request.page_token.is_empty() {
-        None
-    } else {
-        Some(request.page_token.clone())
-    };
+    page_token: &str,
+    keys: &ApiKeyPool,
+    blockchain_names: Vec<String>,
+    cap: usize,
+) -> Result<Fetched<HotAsset>> {
+    let body = asset_request_body(request, page_token, blockchain_names);
+    fetch_all_pages(client, keys, body, ASSET_PAGE_SIZE, cap, "nfts", |resp| {
+        extract_nfts(&request.address[0], resp)
+    })
+    .await
+}
 
-    loop {
-        // 过滤掉None值并收集有效的区块链名称
-        let blockchain_names: Vec<String> = request
-            .blockchain
+fn balances_request_body(request: &AnkrAssetRequest, page_token: &str, blockchain_names: Vec<String>) -> Value {
+    let mut body = asset_request_body(request, page_token, blockchain_names);
+    body["onlyWhitelisted"] = request.only_whitelisted.into();
+    body
+}
+
+fn extract_balances(owner: &str, resp: &Value) -> Vec<HotAsset> {
+    resp.get("assets")
+        .and_then(|t| t.as_array())
+        .map(|assets| {
+            assets
+                .iter()
+                .filter_map(|balance_json| balance_json_to_asset(owner, balance_json))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn extract_nfts(owner: &str, resp: &Value) -> Vec<HotAsset> {
+    resp.get("assets")
+        .and_then(|t| t.as_array())
+        .map(|assets| {
+            assets
+                .iter()
+                .filter_map(|nft_json| nft_json_to_asset(owner, nft_json))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{MockUpstream, TEST_API_KEY};
+    use wiremock::ResponseTemplate;
+    use serde_json::json;
+
+    const OWNER: &str = "0x52908400098527886e0f7030069857d2e4169ee7";
+
+    fn tx_json(hash: &str) -> Value {
+        json!({
+            "hash": hash,
+            "blockNumber": 16,
+            "blockchain": "eth",
+            "timestamp": "0x6500",
+            "from": OWNER,
+            "to": "0x0000000000000000000000000000000000000001",
+            "value": "0x0",
+        })
+    }
+
+    fn tx_request() -> AnkrTxHisRequest {
+        AnkrTxHisRequest {
+            blockchain: vec![PbBlockchain::Eth as i32],
+            address: vec![OWNER.into()],
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn tx_history_proxies_canned_upstream_response() {
+        let upstream = MockUpstream::start().await;
+        upstream
+            .respond_json(
+                json!({ "address": [OWNER] }),
+                json!({
+                    "transactions": [tx_json("0xaa"), tx_json("0xbb")],
+                    "nextPageToken": "",
+                    "syncStatus": { "lag": "-", "status": "synced" },
+                }),
+            )
+            .await;
+        let service = upstream.index_service();
+
+        let mut request = Request::new(tx_request());
+        request
+            .metadata_mut()
+            .insert("x-request-id", MetadataValue::from_static("req-1"));
+        let response = service.get_transaction_history(request).await.unwrap();
+
+        assert_eq!(response.metadata().get("x-request-id").unwrap(), "req-1");
+        assert_eq!(response.metadata().get("x-sync-status").unwrap(), "synced");
+        let list = response.into_inner();
+        let hashes: Vec<_> = list.txs.iter().map(|tx| tx.tx_hash.as_str()).collect();
+        assert_eq!(hashes, ["0xaa", "0xbb"]);
+        assert_eq!(list.txs[0].block_number, "16");
+        assert!(list.next_page_token.is_empty());
+
+        let bodies = upstream.request_bodies().await;
+        assert_eq!(bodies.len(), 1);
+        assert_eq!(bodies[0]["blockchain"], json!(["eth"]));
+        assert_eq!(bodies[0]["pageSize"], json!(TX_PAGE_SIZE));
+    }
+
+    // 第一页带 nextPageToken "p2"，第二页为最后一页；第二页的响应先注册以优先匹配
+    async fn mount_two_tx_pages(upstream: &MockUpstream) {
+        upstream
+            .respond_json(
+                json!({ "pageToken": "p2" }),
+                json!({ "transactions": [tx_json("0xcc"), tx_json("0xdd")], "nextPageToken": "" }),
+            )
+            .await;
+        upstream
+            .respond_json(
+                json!({ "address": [OWNER] }),
+                json!({ "transactions": [tx_json("0xaa"), tx_json("0xbb")], "nextPageToken": "p2" }),
+            )
+            .await;
+    }
+
+    #[tokio::test]
+    async fn transient_page_failure_is_retried() {
+        let upstream = MockUpstream::start().await;
+        upstream.respond_status(json!({ "pageToken": "p2" }), 503, 1).await;
+        mount_two_tx_pages(&upstream).await;
+        let service = upstream.index_service();
+
+        let list = service
+            .get_transaction_history(Request::new(tx_request()))
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(list.txs.len(), 4);
+        assert!(list.next_page_token.is_empty());
+        // 第一页 + 第二页失败一次 + 第二页重试
+        assert_eq!(upstream.request_bodies().await.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn exhausted_retries_return_partial_results_without_caching() {
+        let upstream = MockUpstream::start().await;
+        upstream
+            .respond_status(json!({ "pageToken": "p2" }), 503, UPSTREAM_MAX_ATTEMPTS.into())
+            .await;
+        mount_two_tx_pages(&upstream).await;
+        let service = upstream.index_service();
+
+        let list = service
+            .get_transaction_history(Request::new(tx_request()))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(list.txs.len(), 2);
+        assert_eq!(list.next_page_token, "p2");
+
+        // 部分结果未写入缓存：相同请求再次回源，此时第二页已恢复
+        let list = service
+            .get_transaction_history(Request::new(tx_request()))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(list.txs.len(), 4);
+        assert!(list.next_page_token.is_empty());
+    }
+
+    async fn mount_single_tx_page(upstream: &MockUpstream) {
+        upstream
+            .respond_json(
+                json!({ "address": [OWNER] }),
+                json!({ "transactions": [tx_json("0xaa")], "nextPageToken": "" }),
+            )
+            .await;
+    }
+
+    #[tokio::test]
+    async fn identical_request_is_served_from_cache() {
+        let upstream = MockUpstream::start().await;
+        mount_single_tx_page(&upstream).await;
+        let service = upstream.index_service();
+
+        let first = service
+            .get_transaction_history(Request::new(tx_request()))
+            .await
+            .unwrap()
+            .into_inner();
+        // uuid 不同、地址大小写不同仍是同一查询
+        let second = service
+            .get_transaction_history(Request::new(AnkrTxHisRequest {
+                uuid: "another-client".into(),
+                address: vec![OWNER.to_uppercase().replacen("0X", "0x", 1)],
+                ..tx_request()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(first, second);
+        assert_eq!(upstream.request_bodies().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn no_cache_bypasses_cached_response() {
+        let upstream = MockUpstream::start().await;
+        mount_single_tx_page(&upstream).await;
+        let service = upstream.index_service();
+
+        service
+            .get_transaction_history(Request::new(tx_request()))
+            .await
+            .unwrap();
+        service
+            .get_transaction_history(Request::new(AnkrTxHisRequest {
+                no_cache: true,
+                ..tx_request()
+            }))
+            .await
+            .unwrap();
+        assert_eq!(upstream.request_bodies().await.len(), 2);
+
+        // 强制刷新的结果写回缓存，后续普通请求不再回源
+        service
+            .get_transaction_history(Request::new(tx_request()))
+            .await
+            .unwrap();
+        assert_eq!(upstream.request_bodies().await.len(), 2);
+    }
+
+    fn balance_json(contract: &str) -> Value {
+        json!({
+            "blockchain": "eth",
+            "tokenName": "Token",
+            "tokenSymbol": "TKN",
+            "tokenDecimals": 18,
+            "tokenType": "ERC20",
+            "contractAddress": contract,
+            "thumbnail": "",
+            "balanceUsd": "1.5",
+            "tokenPrice": "0.5",
+        })
+    }
+
+    fn nft_json(token_id: &str) -> Value {
+        json!({
+            "blockchain": "eth",
+            "name": "Punk",
+            "symbol": "PUNK",
+            "tokenId": token_id,
+            "contractAddress": "0x00000000000000000000000000000000000000ff",
+        })
+    }
+
+    fn asset_request(page_token: &str) -> AnkrAssetRequest {
+        AnkrAssetRequest {
+            blockchain: vec![PbBlockchain::Eth as i32],
+            address: vec![OWNER.into()],
+            page_token: page_token.into(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn tx_history_truncated_at_cap_returns_upstream_token() {
+        let upstream = MockUpstream::start().await;
+        upstream
+            .respond_json(
+                json!({ "address": [OWNER] }),
+                json!({ "transactions": [tx_json("0xaa"), tx_json("0xbb")], "nextPageToken": "p2" }),
+            )
+            .await;
+        let service = upstream.index_service_with(|config| config.indexer.tx_history_cap = 2);
+
+        let list = service
+            .get_transaction_history(Request::new(tx_request()))
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(list.txs.len(), 2);
+        assert_eq!(list.next_page_token, "p2");
+        let bodies = upstream.request_bodies().await;
+        // 页大小按剩余额度缩小，截断处与上游 token 对齐
+        assert_eq!(bodies.len(), 1);
+        assert_eq!(bodies[0]["pageSize"], json!(2));
+    }
+
+    #[tokio::test]
+    async fn asset_balance_truncated_at_cap_is_resumable() {
+        let upstream = MockUpstream::start().await;
+        // 只有余额请求带 onlyWhitelisted，先注册以优先匹配
+        upstream
+            .respond_json(
+                json!({ "onlyWhitelisted": false, "pageToken": "b2" }),
+                json!({ "assets": [balance_json("0x03")], "nextPageToken": "" }),
+            )
+            .await;
+        upstream
+            .respond_json(
+                json!({ "onlyWhitelisted": false }),
+                json!({ "assets": [balance_json("0x01"), balance_json("0x02")], "nextPageToken": "b2" }),
+            )
+            .await;
+        upstream
+            .respond_json(
+                json!({ "address": OWNER }),
+                json!({ "assets": [nft_json("1")], "nextPageToken": "" }),
+            )
+            .await;
+        let service = upstream.index_service_with(|config| config.indexer.asset_cap = 2);
+
+        let first = service
+            .get_asset_balance(Request::new(asset_request("")))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(first.assets.len(), 3);
+        assert_eq!(
+            AssetPageToken::decode(&first.next_page_token).unwrap(),
+            AssetPageToken {
+                balances: "b2".into(),
+                nfts: String::new(),
+            }
+        );
+
+        // 续页只请求仍有下一页的余额，NFT 已拉完不再请求
+        let second = service
+            .get_asset_balance(Request::new(asset_request(&first.next_page_token)))
+            .await
+            .unwrap()
+            .into_inner();
+        let contracts: Vec<_> = second.assets.iter().map(|a| a.contract_address.as_str()).collect();
+        assert_eq!(contracts, ["0x03"]);
+        assert!(second.next_page_token.is_empty());
+
+        let bodies = upstream.request_bodies().await;
+        assert_eq!(bodies.len(), 3);
+        assert_eq!(bodies[2]["pageToken"], json!("b2"));
+        assert_eq!(bodies[2]["onlyWhitelisted"], json!(false));
+    }
+
+    #[tokio::test]
+    async fn invalid_asset_page_token_is_rejected() {
+        let upstream = MockUpstream::start().await;
+        let service = upstream.index_service();
+
+        let status = service
+            .get_asset_balance(Request::new(asset_request("not-a-token")))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        assert!(upstream.request_bodies().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn stream_task_counts_as_in_flight_until_finished() {
+        use tokio_stream::StreamExt;
+
+        let upstream = MockUpstream::start().await;
+        mount_two_tx_pages(&upstream).await;
+        let service = upstream.index_service();
+        let in_flight = service.state.in_flight.counter();
+
+        let mut stream = service
+            .stream_transaction_history(Request::new(tx_request()))
+            .await
+            .unwrap()
+            .into_inner();
+        // handler 已返回，但后台任务仍在推送
+        assert_eq!(in_flight.load(Ordering::Acquire), 1);
+
+        let mut txs = 0;
+        while let Some(page) = stream.next().await {
+            txs += page.unwrap().txs.len();
+        }
+        assert_eq!(txs, 4);
+        // channel 关闭后任务可能尚未完全退出，稍等片刻
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(in_flight.load(Ordering::Acquire), 0);
+    }
+
+    fn asset(blockchain: &str, contract_address: &str, token_id: &str, balance: &str) -> HotAsset {
+        HotAsset {
+            blockchain: blockchain.into(),
+            contract_address: contract_address.into(),
+            token_id: token_id.into(),
+            balance: balance.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn dedup_assets_keeps_the_first_occurrence_in_order() {
+        let assets = vec![
+            asset("eth", "0xaa", "", "1"),
+            asset("eth", "0xbb", "7", "1"),
+            asset("eth", "0xaa", "", "2"),
+            // 同一合约的不同 token / 不同链都算不同资产
+            asset("eth", "0xbb", "8", "1"),
+            asset("bsc", "0xaa", "", "3"),
+            asset("eth", "0xbb", "7", "9"),
+        ];
+        let deduped = dedup_assets(assets);
+        let keys: Vec<_> = deduped
             .iter()
-            .filter_map(|&b| blockchain_to_str(&b))
+            .map(|a| (a.blockchain.as_str(), a.contract_address.as_str(), a.token_id.as_str(), a.balance.as_str()))
             .collect();
+        assert_eq!(
+            keys,
+            [("eth", "0xaa", "", "1"), ("eth", "0xbb", "7", "1"), ("eth", "0xbb", "8", "1"), ("bsc", "0xaa", "", "3")]
+        );
+    }
 
-        let mut body = serde_json::json!({
-            "blockchain": blockchain_names,
-            "address": &request.address[0],
-            "pageSize": 50,
-        });
+    fn tx(blockchain: &str, tx_hash: &str, block_number: &str) -> TransactionHistoryEntry {
+        TransactionHistoryEntry {
+            blockchain: blockchain.into(),
+            tx_hash: tx_hash.into(),
+            block_number: block_number.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn dedup_txs_drops_transactions_matched_by_several_addresses() {
+        // 同一笔交易在发送方与接收方两个地址的结果中各出现一次
+        let txs = vec![
+            tx("eth", "0x01", "10"),
+            tx("eth", "0x02", "11"),
+            tx("eth", "0x01", "10"),
+            tx("bsc", "0x01", "10"),
+        ];
+        let deduped = dedup_txs(txs);
+        let keys: Vec<_> = deduped.iter().map(|t| (t.blockchain.as_str(), t.tx_hash.as_str())).collect();
+        assert_eq!(keys, [("eth", "0x01"), ("eth", "0x02"), ("bsc", "0x01")]);
+    }
+
+    fn number(n: u64) -> BlockReference {
+        BlockReference { kind: Some(Kind::Number(n)) }
+    }
+
+    fn named(kind: Kind) -> BlockReference {
+        BlockReference { kind: Some(kind) }
+    }
 
-        // 只有当 current_page_token 是 Some(非空) 时才加 pageToken 字段
-        if let Some(ref token) = current_page_token {
-            body["pageToken"] = serde_json::Value::String(token.clone());
+    #[test]
+    fn block_ref_accepts_plausible_values() {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        for (br, is_timestamp) in [
+            (number(MAX_BLOCK_NUMBER), false),
+            (number(now), true),
+            (named(Kind::Latest("latest".into())), false),
+            (named(Kind::Earliest("earliest".into())), true),
+            (BlockReference { kind: None }, false),
+        ] {
+            assert!(validate_block_ref("from_block", &br, is_timestamp).is_ok(), "{br:?}");
         }
+    }
 
-        // 直接获取JSON响应，而不反序列化为结构体
-        let nft_resp: Value = client
-            .post(endpoint)
-            .json(&body)
-            .send()
-            .await
-            .map_err(AppError::from)?
-            .json()
+    #[test]
+    fn block_ref_rejects_out_of_range_numbers_and_mismatched_names() {
+        let now_millis = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        for (br, is_timestamp) in [
+            (number(MAX_BLOCK_NUMBER + 1), false),
+            // 误传的毫秒时间戳
+            (number(now_millis), true),
+            (named(Kind::Latest("earliest".into())), false),
+            (named(Kind::Earliest("0".into())), false),
+        ] {
+            let status = validate_block_ref("from_block", &br, is_timestamp).unwrap_err();
+            assert_eq!(status.code(), tonic::Code::InvalidArgument, "{br:?}");
+            assert!(status.message().starts_with("from_block"), "{}", status.message());
+        }
+    }
+
+    #[test]
+    fn range_order_accepts_ordered_or_open_ranges() {
+        let earliest = named(Kind::Earliest("earliest".into()));
+        let latest = named(Kind::Latest("latest".into()));
+        for (from, to) in [
+            (Some(&earliest), Some(&latest)),
+            (Some(&number(5)), Some(&number(5))),
+            (Some(&number(5)), Some(&number(6))),
+            (Some(&earliest), Some(&number(0))),
+            (Some(&number(9)), Some(&BlockReference { kind: None })),
+            (Some(&latest), None),
+            (None, Some(&earliest)),
+        ] {
+            assert!(validate_range_order("from_block", from, "to_block", to).is_ok(), "{from:?} {to:?}");
+        }
+    }
+
+    #[test]
+    fn range_order_rejects_inverted_ranges() {
+        let earliest = named(Kind::Earliest("earliest".into()));
+        let latest = named(Kind::Latest("latest".into()));
+        for (from, to) in [
+            (&number(6), &number(5)),
+            (&latest, &number(5)),
+            (&number(5), &earliest),
+            (&latest, &earliest),
+        ] {
+            let status = validate_range_order("from_block", Some(from), "to_block", Some(to)).unwrap_err();
+            assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        }
+        let status = validate_range_order("from_block", Some(&number(6)), "to_block", Some(&number(5))).unwrap_err();
+        assert_eq!(status.message(), "from_block (6) must not be after to_block (5)");
+    }
+
+    #[tokio::test]
+    async fn rejected_api_key_fails_over_to_the_next_key() {
+        let upstream = MockUpstream::start().await;
+        let response = json!({ "transactions": [tx_json("0xaa")], "nextPageToken": "" });
+        upstream
+            .respond_with_for_key("key-a", json!({ "address": [OWNER] }), ResponseTemplate::new(401))
+            .await;
+        let ok = ResponseTemplate::new(200).set_body_json(response);
+        upstream.respond_with_for_key("key-b", json!({ "address": [OWNER] }), ok).await;
+        let service = upstream.index_service_with(|config| {
+            config.ankr_api_keys = vec!["key-a".into(), "key-b".into()];
+        });
+
+        let list = service
+            .get_transaction_history(Request::new(tx_request()))
             .await
-            .map_err(AppError::from)?;
+            .unwrap()
+            .into_inner();
+        assert_eq!(list.txs.len(), 1);
+        assert_eq!(service.state.ankr_keys.auth_failures(), 1);
+        assert_eq!(upstream.request_bodies().await.len(), 2);
+    }
 
-        // 直接从JSON中提取NFT数据
-        if let Some(assets) = nft_resp.get("assets").and_then(|t| t.as_array()) {
-            let page_entries = assets
-                .iter()
-                .filter_map(|nft_json| nft_json_to_asset(&request.address[0], nft_json))
-                .collect::<Vec<_>>();
+    #[tokio::test]
+    async fn concurrent_identical_requests_share_one_upstream_call() {
+        let upstream = MockUpstream::start().await;
+        let slow = ResponseTemplate::new(200)
+            .set_body_json(json!({ "transactions": [tx_json("0xaa")], "nextPageToken": "" }))
+            .set_delay(Duration::from_millis(200));
+        upstream.respond_with_for_key(TEST_API_KEY, json!({ "address": [OWNER] }), slow).await;
+        let service = Arc::new(upstream.index_service());
 
-            all_entries.extend(page_entries);
+        let calls: Vec<_> = (0..8)
+            .map(|_| {
+                let service = service.clone();
+                tokio::spawn(async move { service.get_transaction_history(Request::new(tx_request())).await })
+            })
+            .collect();
+        for call in calls {
+            assert_eq!(call.await.unwrap().unwrap().into_inner().txs.len(), 1);
         }
+        assert_eq!(upstream.request_bodies().await.len(), 1);
+    }
 
-        // 判断是否有下一页
-        let next_page_token = nft_resp
-            .get("nextPageToken")
-            .and_then(|t| t.as_str())
-            .unwrap_or("");
+    #[tokio::test]
+    async fn tx_history_queries_all_requested_addresses() {
+        const OTHER: &str = "0x0000000000000000000000000000000000000002";
+        let upstream = MockUpstream::start().await;
+        let mut to_other = tx_json("0xbb");
+        to_other["from"] = json!(OTHER);
+        upstream
+            .respond_json(
+                json!({ "address": [OWNER, OTHER] }),
+                // 0xaa 同时命中两个地址，上游返回两次
+                json!({ "transactions": [tx_json("0xaa"), to_other, tx_json("0xaa")], "nextPageToken": "" }),
+            )
+            .await;
+        let service = upstream.index_service_with(|config| config.indexer.max_addresses = 2);
 
-        if !next_page_token.is_empty() {
-            current_page_token = Some(next_page_token.to_string());
-        } else {
-            break;
+        let list = service
+            .get_transaction_history(Request::new(AnkrTxHisRequest {
+                address: vec![OWNER.into(), OTHER.into()],
+                ..tx_request()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        let hashes: Vec<_> = list.txs.iter().map(|tx| tx.tx_hash.as_str()).collect();
+        assert_eq!(hashes, ["0xaa", "0xbb"]);
+        assert_eq!(list.txs[1].from, OTHER);
+
+        let status = service
+            .get_transaction_history(Request::new(AnkrTxHisRequest {
+                address: vec![OWNER.into(), OTHER.into(), "0x0000000000000000000000000000000000000003".into()],
+                ..tx_request()
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        assert_eq!(upstream.request_bodies().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn failed_multichain_request_falls_back_to_per_chain_requests() {
+        let upstream = MockUpstream::start().await;
+        // 先注册的优先匹配：多链请求与 polygon 单链请求始终失败，eth 单链请求成功
+        for chains in [json!(["eth", "polygon"]), json!(["polygon"])] {
+            let fragment = json!({ "blockchain": chains });
+            upstream.respond_with_for_key(TEST_API_KEY, fragment, ResponseTemplate::new(503)).await;
         }
+        upstream
+            .respond_json(
+                json!({ "blockchain": ["eth"] }),
+                json!({ "transactions": [tx_json("0xaa")], "nextPageToken": "" }),
+            )
+            .await;
+        let service = upstream.index_service();
+
+        let list = service
+            .get_transaction_history(Request::new(AnkrTxHisRequest {
+                blockchain: vec![PbBlockchain::Eth as i32, PbBlockchain::Polygon as i32],
+                ..tx_request()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        let hashes: Vec<_> = list.txs.iter().map(|tx| tx.tx_hash.as_str()).collect();
+        assert_eq!(hashes, ["0xaa"]);
+        assert_eq!(list.failed_chains, ["polygon"]);
+        assert!(list.next_page_token.is_empty());
+    }
+
+    #[tokio::test]
+    async fn upstream_error_page_maps_to_unavailable() {
+        let upstream = MockUpstream::start().await;
+        let page = ResponseTemplate::new(502).set_body_string("<html><body>Bad Gateway</body></html>");
+        upstream.respond_with_for_key(TEST_API_KEY, json!({ "address": [OWNER] }), page).await;
+        let service = upstream.index_service();
+
+        let status = service
+            .get_transaction_history(Request::new(tx_request()))
+            .await
+            .unwrap_err();
+
+        assert_eq!(status.code(), tonic::Code::Unavailable);
+        assert!(status.message().contains("502"), "{}", status.message());
+        // 5xx 可重试，直到耗尽次数
+        assert_eq!(upstream.request_bodies().await.len(), UPSTREAM_MAX_ATTEMPTS as usize);
+    }
+
+    #[tokio::test]
+    async fn non_json_success_body_maps_to_unavailable() {
+        let upstream = MockUpstream::start().await;
+        let body = ResponseTemplate::new(200).set_body_string("not json");
+        upstream.respond_with_for_key(TEST_API_KEY, json!({ "address": [OWNER] }), body).await;
+        let service = upstream.index_service();
+
+        let status = service
+            .get_transaction_history(Request::new(tx_request()))
+            .await
+            .unwrap_err();
+
+        assert_eq!(status.code(), tonic::Code::Unavailable);
+        assert!(status.message().contains("non-JSON"), "{}", status.message());
+        assert!(status.message().contains("status 200"), "{}", status.message());
+    }
 
-        if all_entries.len() >= 1000 {
-            break;
+    fn upstream_limits(acquire_timeout: Duration) -> Arc<UpstreamLimits> {
+        Arc::new(UpstreamLimits {
+            max_concurrency: 1,
+            acquire_timeout,
+            allowed_hosts: Vec::new(),
+            hosts: DashMap::new(),
+        })
+    }
+
+    #[tokio::test]
+    async fn upstream_permits_serialize_requests_per_host() {
+        let limits = upstream_limits(Duration::from_secs(5));
+        let delay = Duration::from_millis(100);
+        let start = Instant::now();
+
+        let tasks: Vec<_> = (0..2)
+            .map(|_| {
+                let limits = limits.clone();
+                tokio::spawn(async move {
+                    let _permit = limits.acquire("rpc.ankr.com").await.unwrap();
+                    tokio::time::sleep(delay).await;
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
         }
+
+        assert!(start.elapsed() >= delay * 2, "{:?}", start.elapsed());
+        // 其他 host 使用独立的信号量
+        let _permit = limits.acquire("rpc.ankr.com").await.unwrap();
+        let _other = limits.acquire("other.example").await.unwrap();
     }
 
-    Ok(all_entries)
-}
\ No newline at end of file
+    #[tokio::test]
+    async fn upstream_permit_timeout_is_resource_exhausted() {
+        let limits = upstream_limits(Duration::from_millis(50));
+        let held = limits.acquire("rpc.ankr.com").await.unwrap();
+
+        let status = to_status(limits.acquire("rpc.ankr.com").await.unwrap_err());
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+
+        drop(held);
+        let _permit = limits.acquire("rpc.ankr.com").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn upstream_span_is_exported_as_child_of_indexer_span() {
+        use opentelemetry::trace::TracerProvider as _;
+        use opentelemetry_sdk::trace::{InMemorySpanExporter, SdkTracerProvider};
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let exporter = InMemorySpanExporter::default();
+        let provider = SdkTracerProvider::builder().with_simple_exporter(exporter.clone()).build();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let upstream = MockUpstream::start().await;
+        mount_single_tx_page(&upstream).await;
+        upstream
+            .index_service()
+            .get_transaction_history(Request::new(tx_request()))
+            .await
+            .unwrap();
+
+        provider.force_flush().unwrap();
+        let spans = exporter.get_finished_spans().unwrap();
+        let parent = spans.iter().find(|span| span.name == "indexer").expect("indexer span");
+        let child = spans.iter().find(|span| span.name == "upstream_request").expect("upstream span");
+        assert_eq!(child.parent_span_id, parent.span_context.span_id());
+        assert_eq!(child.span_context.trace_id(), parent.span_context.trace_id());
+    }
+}
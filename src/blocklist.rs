@@ -0,0 +1,206 @@
+// src/blocklist.rs
+use crate::error::{AppError, Result};
+use arc_swap::ArcSwap;
+use ip_network::IpNetwork;
+use ip_network_table::IpNetworkTable;
+use once_cell::sync::OnceCell;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tonic::Status;
+use tracing::{debug, info, warn};
+
+/// 客户端 IP 黑名单：从文件加载 CIDR 列表（如滥用 ASN、地区网段），按最长前缀匹配；
+/// 文件修改后由 ip_blocklist_reload_task 重新加载，加载失败时保留旧列表
+pub struct IpBlocklist {
+    path: String,
+    table: ArcSwap<IpNetworkTable<()>>,
+    // 最近一次尝试加载时文件的修改时间，用于跳过未变化的文件
+    modified: Mutex<Option<SystemTime>>,
+}
+
+// 启动时通过 init_ip_blocklist 设置；未设置时不做检查
+static IP_BLOCKLIST: OnceCell<Arc<IpBlocklist>> = OnceCell::new();
+
+// 因命中黑名单被拒绝的请求数，由 runtime_metrics_task 采样
+static BLOCKED_REQUESTS: AtomicU64 = AtomicU64::new(0);
+
+pub fn init_ip_blocklist(blocklist: Arc<IpBlocklist>) {
+    let _ = IP_BLOCKLIST.set(blocklist);
+}
+
+pub fn blocked_requests() -> u64 {
+    BLOCKED_REQUESTS.load(Ordering::Relaxed)
+}
+
+// 文件格式：每行一个 CIDR 或单个 IP，# 之后为注释，空行忽略；主机位非零的 CIDR 按前缀截断
+fn parse_blocklist(content: &str) -> std::result::Result<IpNetworkTable<()>, String> {
+    let mut table = IpNetworkTable::new();
+    for (index, line) in content.lines().enumerate() {
+        let entry = line.split('#').next().unwrap_or_default().trim();
+        if entry.is_empty() {
+            continue;
+        }
+        let network = if entry.contains('/') {
+            IpNetwork::from_str_truncate(entry).ok()
+        } else {
+            entry.parse::<IpAddr>().ok().map(IpNetwork::from)
+        };
+        let network = network
+            .ok_or_else(|| format!("invalid blocklist entry on line {}: {:?}", index + 1, entry))?;
+        table.insert(network, ());
+    }
+    Ok(table)
+}
+
+async fn read_blocklist(path: &str) -> Result<(IpNetworkTable<()>, Option<SystemTime>)> {
+    let modified = tokio::fs::metadata(path).await?.modified().ok();
+    let content = tokio::fs::read_to_string(path).await?;
+    let table = parse_blocklist(&content).map_err(|e| AppError::Custom(format!("{}: {}", path, e)))?;
+    Ok((table, modified))
+}
+
+impl IpBlocklist {
+    /// 加载黑名单文件；文件不存在或格式错误时报错，由调用方决定是否终止启动
+    pub async fn load(path: &str) -> Result<Self> {
+        let (table, modified) = read_blocklist(path).await?;
+        let (ipv4_ranges, ipv6_ranges) = table.len();
+        info!(path, ipv4_ranges, ipv6_ranges, "IP blocklist loaded");
+        Ok(Self {
+            path: path.to_string(),
+            table: ArcSwap::from_pointee(table),
+            modified: Mutex::new(modified),
+        })
+    }
+
+    // 文件修改时间变化时重新加载并原子替换，进行中的查询不受影响
+    async fn reload_if_changed(&self) -> Result<()> {
+        let modified = tokio::fs::metadata(&self.path).await?.modified().ok();
+        {
+            let mut last = self.modified.lock().unwrap_or_else(|e| e.into_inner());
+            if modified.is_some() && *last == modified {
+                return Ok(());
+            }
+            // 无论加载成功与否都记录，格式错误的文件在下次修改前只告警一次
+            *last = modified;
+        }
+        let (table, _) = read_blocklist(&self.path).await?;
+        let (ipv4_ranges, ipv6_ranges) = table.len();
+        self.table.store(Arc::new(table));
+        info!(path = %self.path, ipv4_ranges, ipv6_ranges, "IP blocklist reloaded");
+        Ok(())
+    }
+
+    /// 返回命中的最长前缀网段
+    pub fn longest_match(&self, ip: IpAddr) -> Option<IpNetwork> {
+        self.table.load().longest_match(ip).map(|(network, _)| network)
+    }
+}
+
+/// 客户端 IP 命中黑名单时返回 permission_denied；未启用黑名单或 IP 无法解析时放行
+pub fn check_ip_blocklist(ip: &str) -> std::result::Result<(), Status> {
+    let Some(blocklist) = IP_BLOCKLIST.get() else {
+        return Ok(());
+    };
+    let Ok(addr) = ip.parse::<IpAddr>() else {
+        return Ok(());
+    };
+    if let Some(network) = blocklist.longest_match(addr) {
+        BLOCKED_REQUESTS.fetch_add(1, Ordering::Relaxed);
+        debug!(%ip, %network, "rejected request from blocklisted IP range");
+        return Err(Status::permission_denied("client IP is blocked"));
+    }
+    Ok(())
+}
+
+// 定期检查黑名单文件是否变化；未启用黑名单时永不返回
+pub async fn ip_blocklist_reload_task(blocklist: Option<Arc<IpBlocklist>>, period: Duration) -> Result<()> {
+    let Some(blocklist) = blocklist else {
+        return std::future::pending().await;
+    };
+    let mut interval = tokio::time::interval(period);
+    // 第一次 tick 立即返回，启动时已加载过
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        if let Err(e) = blocklist.reload_if_changed().await {
+            warn!(error = %e, "failed to reload IP blocklist, keeping previous list");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::io::{Seek, Write};
+
+    fn write_blocklist(file: &mut File, content: &str, modified: SystemTime) {
+        file.set_len(0).unwrap();
+        file.rewind().unwrap();
+        file.write_all(content.as_bytes()).unwrap();
+        // 显式设置修改时间，避免同一秒内写入导致 mtime 不变
+        file.set_modified(modified).unwrap();
+    }
+
+    #[test]
+    fn parse_skips_comments_and_truncates_host_bits() {
+        let Ok(table) = parse_blocklist("# abuse ranges\n\n198.51.100.7/24 # host bits set\n2001:db8::1\n") else {
+            panic!("valid blocklist rejected");
+        };
+        assert_eq!(table.len(), (1, 1));
+        assert!(table.longest_match("198.51.100.200".parse::<IpAddr>().unwrap()).is_some());
+    }
+
+    #[test]
+    fn parse_rejects_invalid_entry() {
+        let Err(err) = parse_blocklist("10.0.0.0/8\nnot-an-ip\n") else {
+            panic!("invalid entry accepted");
+        };
+        assert!(err.contains("line 2"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn blocked_cidr_matches_and_other_ips_pass() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        write_blocklist(file.as_file_mut(), "203.0.113.0/24\n2001:db8:dead::/48\n", SystemTime::now());
+        let blocklist = IpBlocklist::load(file.path().to_str().unwrap()).await.unwrap();
+
+        assert_eq!(
+            blocklist.longest_match("203.0.113.9".parse().unwrap()),
+            Some("203.0.113.0/24".parse().unwrap())
+        );
+        assert!(blocklist.longest_match("2001:db8:dead::1".parse().unwrap()).is_some());
+        assert!(blocklist.longest_match("203.0.114.1".parse().unwrap()).is_none());
+        assert!(blocklist.longest_match("2001:db8:beef::1".parse().unwrap()).is_none());
+
+        init_ip_blocklist(Arc::new(blocklist));
+        let status = check_ip_blocklist("203.0.113.200").unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+        assert!(check_ip_blocklist("192.0.2.1").is_ok());
+        // 无法解析的 IP 放行，由后续的身份与限流检查处理
+        assert!(check_ip_blocklist("unknown").is_ok());
+    }
+
+    #[tokio::test]
+    async fn reload_picks_up_changes_and_keeps_old_list_on_error() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        let path = file.path().to_str().unwrap().to_string();
+        let start = SystemTime::now() - Duration::from_secs(60);
+        write_blocklist(file.as_file_mut(), "198.51.100.0/24\n", start);
+        let blocklist = IpBlocklist::load(&path).await.unwrap();
+        assert!(blocklist.longest_match("198.51.100.1".parse().unwrap()).is_some());
+
+        write_blocklist(file.as_file_mut(), "192.0.2.0/24\n", start + Duration::from_secs(10));
+        blocklist.reload_if_changed().await.unwrap();
+        assert!(blocklist.longest_match("198.51.100.1".parse().unwrap()).is_none());
+        assert!(blocklist.longest_match("192.0.2.1".parse().unwrap()).is_some());
+
+        write_blocklist(file.as_file_mut(), "garbage\n", start + Duration::from_secs(20));
+        assert!(blocklist.reload_if_changed().await.is_err());
+        assert!(blocklist.longest_match("192.0.2.1".parse().unwrap()).is_some());
+        // 未再修改时不重复加载（也不重复报错）
+        blocklist.reload_if_changed().await.unwrap();
+    }
+}
@@ -1,48 +1,174 @@
 // client.rs
 
-use crate::rules::{RULE_REGISTRY};  
+use crate::db::AuthEvent;
+use crate::rules::{IpPolicy, RULE_REGISTRY};  
+use crate::config::ConnectionConfig;
+use arc_swap::ArcSwapOption;
 use dashmap::DashMap;  
-use governor::{RateLimiter, state::direct::NotKeyed, clock::DefaultClock};  
-use moka::future::Cache;  
-use once_cell::sync::Lazy;  
-use std::sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}};  
+use governor::{Quota, RateLimiter, state::direct::NotKeyed, clock::{Clock, DefaultClock}};  
+use moka::{future::Cache, notification::RemovalCause, ops::compute::{CompResult, Op}};  
+use once_cell::sync::{Lazy, OnceCell};  
+use std::sync::{Arc, Mutex, atomic::{AtomicBool, AtomicU64, Ordering}};  
 use std::time::{Duration, Instant};  
+use tokio::sync::mpsc::{self, error::TrySendError};
 use tonic::Status;  
+use tracing::{debug, info, warn};
 
 // 类型别名：具体的令牌桶类型  
-type SharedBucket = Arc<RateLimiter<NotKeyed, governor::state::InMemoryState, DefaultClock>>;
+type Limiter = RateLimiter<NotKeyed, governor::state::InMemoryState, DefaultClock>;
+type SharedBucket = Arc<ServiceBucket>;
+// 单个客户端的全部令牌桶：Key 是服务名 (如 "ankr_index")
+type ClientBuckets = DashMap<String, SharedBucket>;
+
+// 单调时钟基准：last_active 存储为相对该基准的毫秒数，便于用原子量无锁读写
+static MONOTONIC_BASE: Lazy<Instant> = Lazy::new(Instant::now);
+
+fn monotonic_millis() -> u64 {
+    MONOTONIC_BASE.elapsed().as_millis() as u64
+}
+
+// 审计日志中只记录 UUID 前缀，避免完整标识落入日志
+fn uuid_prefix(uuid: &str) -> &str {
+    uuid.get(..8).unwrap_or(uuid)
+}
+
+// 审计事件发送端，未设置数据库时为空
+static AUTH_EVENTS: OnceCell<mpsc::Sender<AuthEvent>> = OnceCell::new();
+
+pub fn init_auth_events(tx: mpsc::Sender<AuthEvent>) {
+    let _ = AUTH_EVENTS.set(tx);
+}
+
+// 非阻塞投递审计事件，通道已满时丢弃并告警
+fn record_auth_event(event: AuthEvent) {
+    if let Some(tx) = AUTH_EVENTS.get()
+        && let Err(TrySendError::Full(event)) = tx.try_send(event)
+    {
+        warn!(event_type = event.event_type, "auth event channel full, dropping event");
+    }
+}
+
+// 令牌桶及其理论到达时间（GCRA 的 TAT）
+// governor 无法在不扣减的情况下查询桶状态，因此每次 check 时同步一份 TAT：
+// 放行时按 GCRA 推进，拒绝时由 NotUntil 给出的等待时间精确还原，查询时据此推算
+pub struct ServiceBucket {
+    limiter: Limiter,
+    quota: Quota,
+    // 桶补满的时刻（相对 MONOTONIC_BASE 的纳秒数），不晚于当前时间即为满桶
+    tat: AtomicU64,
+}
+
+// 限流状态查询结果
+#[derive(Debug, Clone)]
+pub struct BucketStatus {
+    pub burst: u32,
+    pub replenish_interval: Duration,
+    pub available: u32,
+    // 距离桶补满的时间
+    pub full_in: Duration,
+}
+
+impl BucketStatus {
+    // 尚未创建令牌桶时的满桶状态
+    fn full(quota: Quota) -> Self {
+        Self {
+            burst: quota.burst_size().get(),
+            replenish_interval: quota.replenish_interval(),
+            available: quota.burst_size().get(),
+            full_in: Duration::ZERO,
+        }
+    }
+}
+
+fn monotonic_nanos() -> u64 {
+    MONOTONIC_BASE.elapsed().as_nanos() as u64
+}
+
+impl ServiceBucket {
+    fn new(quota: Quota) -> Self {
+        Self {
+            limiter: RateLimiter::direct(quota),
+            quota,
+            tat: AtomicU64::new(0),
+        }
+    }
+
+    fn interval_nanos(&self) -> u64 {
+        (self.quota.replenish_interval().as_nanos() as u64).max(1)
+    }
+
+    // 扣除一个令牌并同步 TAT，成功返回 true
+    fn check(&self) -> bool {
+        let interval = self.interval_nanos();
+        match self.limiter.check() {
+            Ok(()) => {
+                let now = monotonic_nanos();
+                let _ = self.tat.fetch_update(Ordering::AcqRel, Ordering::Acquire, |tat| {
+                    Some(tat.max(now) + interval)
+                });
+                true
+            }
+            Err(not_until) => {
+                // 下一个令牌到达后桶里还差 burst - 1 个令牌
+                let wait = not_until.wait_time_from(self.limiter.clock().now()).as_nanos() as u64;
+                let burst = self.quota.burst_size().get() as u64;
+                self.tat.store(monotonic_nanos() + wait + (burst - 1) * interval, Ordering::Release);
+                false
+            }
+        }
+    }
+
+    // 根据 TAT 推算当前可用令牌数（不扣减）
+    pub fn status(&self) -> BucketStatus {
+        let burst = self.quota.burst_size().get();
+        let interval = self.interval_nanos();
+        let full_in = self.tat.load(Ordering::Acquire).saturating_sub(monotonic_nanos());
+        // 距补满还差的令牌数（向上取整），其余即为可用
+        let missing = full_in.div_ceil(interval).min(burst as u64) as u32;
+        BucketStatus {
+            burst,
+            replenish_interval: self.quota.replenish_interval(),
+            available: burst - missing,
+            full_in: Duration::from_nanos(full_in),
+        }
+    }
+}
 
 // 单个用户的状态  
 pub struct ClientState {  
-    // Sticky IP  
-    pub bound_ip: Mutex<Option<String>>,  
+    // Sticky IP（无锁读写，首次绑定使用 CAS）；非 Strict 策略下为最近一次请求的 IP
+    pub bound_ip: ArcSwapOption<String>,
+    // AllowN 策略：近期出现过的 IP 及最后出现时间（相对 MONOTONIC_BASE 的毫秒数）
+    recent_ips: Mutex<Vec<(String, u64)>>,
     // 连接是否活跃
     is_connected: AtomicBool,
-    // 动态桶：Key 是服务名 (如 "ankr_index")  
-    pub buckets: DashMap<String, SharedBucket>,
-    // 最后活跃时间，用于心跳检测
-    last_active: Mutex<Instant>,
+    // 动态桶：与 GlobalStateManager.buckets 中的同一份共享，连接过期后仍保留
+    pub buckets: Arc<ClientBuckets>,
+    // 最后活跃时间（相对 MONOTONIC_BASE 的毫秒数），用于心跳检测
+    last_active: AtomicU64,
 }
 
 impl ClientState {  
-    fn new() -> Self {  
+    fn new(buckets: Arc<ClientBuckets>) -> Self {  
         Self {  
-            bound_ip: Mutex::new(None),  
+            bound_ip: ArcSwapOption::empty(),
+            recent_ips: Mutex::new(Vec::new()),
             is_connected: AtomicBool::new(false),
-            buckets: DashMap::new(),  
-            last_active: Mutex::new(Instant::now()),
+            buckets,
+            last_active: AtomicU64::new(monotonic_millis()),
         }  
     }  
     
     // 更新最后活跃时间
     pub fn update_last_active(&self) {
-        *self.last_active.lock().unwrap() = Instant::now();
+        // fetch_max 保证并发更新时时间戳只会前进
+        self.last_active.fetch_max(monotonic_millis(), Ordering::AcqRel);
     }
     
-    // 检查连接是否超时（超过60秒无活动）
-    pub fn is_expired(&self) -> bool {
-        let last = self.last_active.lock().unwrap();
-        last.elapsed() > Duration::from_secs(60)
+    // 检查连接是否超时（超过 threshold 无活动）
+    pub fn is_expired(&self, threshold: Duration) -> bool {
+        let last = self.last_active.load(Ordering::Acquire);
+        monotonic_millis().saturating_sub(last) > threshold.as_millis() as u64
     }
     
     // 标记连接为活跃状态
@@ -60,6 +186,102 @@ impl ClientState {
         self.is_connected.load(Ordering::Acquire)
     }
 
+    // 按策略校验并更新 UUID 的 IP 绑定
+    fn bind_ip(&self, uuid: &str, ip: &str, policy: IpPolicy) -> Result<(), Status> {
+        match policy {
+            IpPolicy::Strict => self.bind_ip_strict(uuid, ip),
+            IpPolicy::AllowRoaming => {
+                self.bind_ip_roaming(uuid, ip);
+                Ok(())
+            }
+            IpPolicy::AllowN { max_ips, window } => self.bind_ip_allow_n(uuid, ip, max_ips, window),
+        }
+    }
+
+    fn bind_ip_strict(&self, uuid: &str, ip: &str) -> Result<(), Status> {
+        // 仅在尚未绑定时写入；若已绑定则返回旧值用于比对
+        let previous = self.bound_ip.compare_and_swap(&None::<Arc<String>>, Some(Arc::new(ip.to_string())));
+        match previous.as_ref() {
+            Some(bound) if **bound != ip => {
+                warn!(
+                    uuid = uuid_prefix(uuid),
+                    bound_ip = %bound,
+                    attempted_ip = %ip,
+                    "rejected request from IP not bound to UUID"
+                );
+                record_auth_event(AuthEvent::new(uuid, bound, ip, "ip_mismatch"));
+                return Err(Status::permission_denied("UUID bound to different IP"));
+            }
+            Some(_) => {}
+            None => {
+                info!(uuid = uuid_prefix(uuid), ip = %ip, "bound UUID to IP");
+                record_auth_event(AuthEvent::new(uuid, ip, ip, "ip_bind"));
+            }
+        }
+        Ok(())
+    }
+
+    fn bind_ip_roaming(&self, uuid: &str, ip: &str) {
+        let previous = self.bound_ip.swap(Some(Arc::new(ip.to_string())));
+        match previous.as_deref() {
+            Some(bound) if bound != ip => {
+                info!(uuid = uuid_prefix(uuid), from_ip = %bound, to_ip = %ip, "UUID roamed to new IP");
+                record_auth_event(AuthEvent::new(uuid, bound, ip, "ip_roam"));
+            }
+            Some(_) => {}
+            None => {
+                info!(uuid = uuid_prefix(uuid), ip = %ip, "bound UUID to IP");
+                record_auth_event(AuthEvent::new(uuid, ip, ip, "ip_bind"));
+            }
+        }
+    }
+
+    fn bind_ip_allow_n(&self, uuid: &str, ip: &str, max_ips: usize, window: Duration) -> Result<(), Status> {
+        let now = monotonic_millis();
+        let window_ms = window.as_millis() as u64;
+        let bound = self.bound_ip.load_full();
+        let bound = bound.as_deref().map_or(ip, String::as_str);
+
+        let mut recent = self.recent_ips.lock().unwrap_or_else(|e| e.into_inner());
+        recent.retain(|(_, seen)| now.saturating_sub(*seen) <= window_ms);
+        if let Some(entry) = recent.iter_mut().find(|(known, _)| known == ip) {
+            entry.1 = now;
+        } else if recent.len() < max_ips {
+            recent.push((ip.to_string(), now));
+            info!(uuid = uuid_prefix(uuid), ip = %ip, ip_count = recent.len(), "bound UUID to additional IP");
+            record_auth_event(AuthEvent::new(uuid, bound, ip, "ip_bind"));
+        } else {
+            warn!(
+                uuid = uuid_prefix(uuid),
+                bound_ip = %bound,
+                attempted_ip = %ip,
+                max_ips,
+                "rejected request: UUID reached its distinct IP limit"
+            );
+            record_auth_event(AuthEvent::new(uuid, bound, ip, "ip_mismatch"));
+            return Err(Status::permission_denied("UUID bound to too many IPs"));
+        }
+        drop(recent);
+
+        self.bound_ip.store(Some(Arc::new(ip.to_string())));
+        Ok(())
+    }
+
+    // 只读校验：ip 是否符合当前绑定（不更新绑定，也不记录审计事件）
+    fn ip_allowed(&self, ip: &str, policy: IpPolicy) -> bool {
+        match policy {
+            IpPolicy::Strict | IpPolicy::AllowRoaming => {
+                self.bound_ip.load().as_deref().is_some_and(|bound| bound == ip)
+            }
+            IpPolicy::AllowN { window, .. } => {
+                let now = monotonic_millis();
+                let window_ms = window.as_millis() as u64;
+                let recent = self.recent_ips.lock().unwrap_or_else(|e| e.into_inner());
+                recent.iter().any(|(known, seen)| known == ip && now.saturating_sub(*seen) <= window_ms)
+            }
+        }
+    }
+
     // 获取(或懒加载)指定服务的令牌桶  
     pub fn get_bucket_for_service(&self, service_name: &str) -> Result<SharedBucket, Status> {
         // 如果已经存在，直接返回  
@@ -72,7 +294,7 @@ impl ClientState {
             .ok_or_else(|| Status::internal(format!("Rule not found for service: {}", service_name)))?;
 
         // 创建新桶  
-        let new_bucket = Arc::new(RateLimiter::direct(rule.quota));  
+        let new_bucket = Arc::new(ServiceBucket::new(rule.quota));  
         self.buckets.insert(service_name.to_string(), new_bucket.clone());  
           
         Ok(new_bucket)  
@@ -83,136 +305,440 @@ impl ClientState {
         let bucket = self.get_bucket_for_service(service_name)?;
         
         // 检查并消费一个令牌，如果失败则返回错误
-        bucket.check().map_err(|_| Status::resource_exhausted(format!("Rate limit exceeded for service: {}", service_name)))?;
+        if !bucket.check() {
+            return Err(Status::resource_exhausted(format!("Rate limit exceeded for service: {}", service_name)));
+        }
         Ok(())
     }
      
 }  
 
-// 全局用户状态缓存  
-pub static GLOBAL_STATE: Lazy<GlobalStateManager> = Lazy::new(GlobalStateManager::new);  
+// 连接生命周期配置，需在首次访问 GLOBAL_STATE 之前通过 init_connection_config 设置
+static CONNECTION_CONFIG: OnceCell<ConnectionConfig> = OnceCell::new();
+
+pub fn init_connection_config(config: ConnectionConfig) {
+    let _ = CONNECTION_CONFIG.set(config);
+}
 
-// 全局活跃连接列表，用于心跳检测
-pub static ACTIVE_CONNECTIONS: Lazy<DashMap<String, Instant>> = Lazy::new(DashMap::new);
+// 因容量上限被淘汰的客户端状态数（区别于 idle 过期），由 runtime_metrics_task 采样
+static CAPACITY_EVICTIONS: AtomicU64 = AtomicU64::new(0);
+
+pub fn capacity_evictions() -> u64 {
+    CAPACITY_EVICTIONS.load(Ordering::Relaxed)
+}
+
+// moka 淘汰 ClientState 时的清理：标记断开并记录原因
+// 被替换（Replaced）的条目仍由新状态接管，不做处理
+fn on_client_evicted(uuid: Arc<String>, state: Arc<ClientState>, cause: RemovalCause) {
+    if cause == RemovalCause::Replaced {
+        return;
+    }
+    state.mark_disconnected();
+    match cause {
+        RemovalCause::Size => {
+            CAPACITY_EVICTIONS.fetch_add(1, Ordering::Relaxed);
+            warn!(uuid = uuid_prefix(&uuid), "client state evicted: store at capacity");
+        }
+        _ => debug!(uuid = uuid_prefix(&uuid), ?cause, "client state evicted"),
+    }
+}
+
+// 全局用户状态缓存  
+pub static GLOBAL_STATE: Lazy<GlobalStateManager> = Lazy::new(|| {
+    GlobalStateManager::new(CONNECTION_CONFIG.get().cloned().unwrap_or_default())
+});
 
 pub struct GlobalStateManager {  
-    // 10分钟无操作自动过期  
+    // 超过 idle_ttl 无操作自动过期
     store: Cache<String, Arc<ClientState>>,  
+    // 令牌桶单独按 UUID 保存，连接过期（store 中移除）后重连不会重新获得满桶
+    buckets: Cache<String, Arc<ClientBuckets>>,
+    config: ConnectionConfig,
 }
 
 impl GlobalStateManager {  
-    fn new() -> Self {  
+    fn new(config: ConnectionConfig) -> Self {  
         Self {  
             store: Cache::builder()  
-                .time_to_idle(Duration::from_secs(600)) // 10分钟 idle 清除  
+                .time_to_idle(config.idle_ttl)
+                .max_capacity(config.max_clients)
+                .eviction_listener(on_client_evicted)
                 .build(),  
+            buckets: Cache::builder()
+                .time_to_idle(config.idle_ttl)
+                .max_capacity(config.max_clients)
+                .build(),
+            config,
         }  
     }  
+
+    // 客户端的令牌桶集合，不存在时创建
+    async fn client_buckets(&self, uuid: &str) -> Arc<ClientBuckets> {
+        self.buckets.get_with_by_ref(uuid, async { Arc::new(DashMap::new()) }).await
+    }
     
-    // 处理连接请求，验证UUID并建立ClientState
-    pub async fn update_client_state(&self, uuid: String, ip: String) -> Result<(), Status> {
-  
-        let state = self.store.get_with(uuid.clone(), async { Arc::new(ClientState::new()) }).await;
+    // 拦截器入口：取得（或原子地创建）ClientState，按 ip_policy 校验 IP，通过后才扣除令牌。
+    // 被 IP 策略拒绝的请求不会消耗已绑定客户端的配额；首次请求同样扣除令牌，
+    // 令牌桶沿用该 UUID 之前的桶，连接过期后重连不会重新获得满桶。
+    // 同一 UUID 的并发首次请求共享同一个新建状态，只有一个 IP 能完成绑定，其余按策略校验
+    pub async fn admit_request(&self, uuid: &str, ip: &str, service_name: &str) -> Result<(), Status> {
+        let rule = RULE_REGISTRY.get(service_name)
+            .ok_or_else(|| Status::internal(format!("Rule not found for service: {}", service_name)))?;
+        let state = self
+            .store
+            .get_with_by_ref(uuid, async { Arc::new(ClientState::new(self.client_buckets(uuid).await)) })
+            .await;
         state.update_last_active();
-        ACTIVE_CONNECTIONS.insert(uuid.clone(), Instant::now());
-        let mut ip_guard = state.bound_ip.lock().unwrap();  
-        if let Some(ref bound) = *ip_guard {  
-            if bound != &ip {  
-                return Err(Status::permission_denied("UUID bound to different IP"));  
-            }  
-        } else {  
-            *ip_guard = Some(ip);  
-        }  
-        drop(ip_guard); 
+        state.bind_ip(uuid, ip, rule.ip_policy)?;
         state.mark_connected();
-        Ok(())  
+        state.try_consume_token(service_name)
     }
 
-    pub async fn init_client_state(&self, uuid: &str, ip: &str, service_name: &str) -> Result<(), Status> {
-        let rule = RULE_REGISTRY.get(service_name)  
-            .ok_or_else(|| Status::internal(format!("Rule not found for service: {}", service_name)))?;  
-        let new_bucket = Arc::new(RateLimiter::direct(rule.quota));  
-        
-        let client_state = ClientState{
-            bound_ip: Mutex::new(Some(ip.to_string())),
-            is_connected: AtomicBool::new(true),
-            buckets: {
-                let buckets = DashMap::new();
-                buckets.insert(service_name.to_string(), new_bucket);
-                buckets
-            },
-            last_active: Mutex::new(Instant::now()),
-        };
-        self.store.insert(uuid.to_string(), Arc::new(client_state)).await;
-        // 注意：client_state 是 ClientState 的实例，不是 Arc 包装的
-        // 我们需要从 store 中获取 Arc 包装的实例来调用方法
-        if let Some(stored_client_state) = self.store.get(uuid).await {
-            stored_client_state.mark_connected();
-            stored_client_state.update_last_active();
-            ACTIVE_CONNECTIONS.insert(uuid.to_string(), Instant::now());
+    // 查询某个客户端在指定服务上的限流状态（只读，不创建任何状态）
+    // 与 gRPC 路径相同的身份要求：UUID 须已建立连接，且请求 IP 符合该服务 ip_policy 下的绑定
+    pub async fn rate_limit_status(&self, uuid: &str, ip: &str, service_name: &str) -> Result<BucketStatus, Status> {
+        let rule = RULE_REGISTRY.get(service_name)
+            .ok_or_else(|| Status::not_found(format!("Rule not found for service: {}", service_name)))?;
+        let state = self.store.get(uuid).await
+            .ok_or_else(|| Status::not_found("Unknown client"))?;
+        if !state.ip_allowed(ip, rule.ip_policy) {
+            return Err(Status::permission_denied("UUID bound to different IP"));
         }
-        Ok(())
+        // 尚未在该服务上消耗过令牌时视为满桶
+        Ok(match state.buckets.get(service_name) {
+            Some(bucket) => bucket.status(),
+            None => BucketStatus::full(rule.quota),
+        })
     }
-  
-
-  
-    // 连接断开时调用  
-    pub async fn release_conn(&self, uuid: &str) {  
-        if let Some(state) = self.store.get(uuid).await {  
-            state.mark_disconnected();
-            // 从活跃连接列表中移除
-            ACTIVE_CONNECTIONS.remove(uuid);
-            // moka 会自动处理 time_to_idle  
-        }  
-    }  
 
     // 获取缓存存储，用于外部清理任务
     pub fn get_store(&self) -> &Cache<String, Arc<ClientState>> {
         &self.store
     }
     
-    // 清理过期连接
+    // 当前处于连接状态的客户端数
+    pub fn active_connections(&self) -> usize {
+        self.store.iter().filter(|(_, state)| state.is_connected()).count()
+    }
+    
+    // 清理过期连接：以 ClientState.last_active 为唯一依据，
+    // 过期的客户端从 store 移除（释放 IP 绑定，由淘汰回调标记断开）；令牌桶保留在 buckets 中
     pub async fn cleanup_expired_connections(&self) {
-        let now = Instant::now();
-        let mut expired_uuids = Vec::new();
-        
-        // 找出所有过期的连接
-        for entry in ACTIVE_CONNECTIONS.iter() {
-            let uuid = entry.key();
-            let last_active = entry.value();
-            
-            if now.duration_since(*last_active) > Duration::from_secs(60) {
-                expired_uuids.push(uuid.clone());
+        let threshold = self.config.expiry_threshold;
+        let expired: Vec<Arc<String>> = self
+            .store
+            .iter()
+            .filter(|(_, state)| state.is_expired(threshold))
+            .map(|(uuid, _)| uuid)
+            .collect();
+
+        for uuid in expired {
+            // 收集之后可能已重新活跃：在 entry 锁内再确认一次，与并发请求互斥
+            let result = self
+                .store
+                .entry_by_ref(uuid.as_str())
+                .and_compute_with(|entry| async move {
+                    match entry {
+                        Some(entry) if entry.value().is_expired(threshold) => Op::Remove,
+                        _ => Op::Nop,
+                    }
+                })
+                .await;
+            if let CompResult::Removed(entry) = result {
+                // 重新写入以刷新闲置计时，桶在断开后至少保留 idle_ttl
+                self.buckets.insert(uuid.to_string(), entry.value().buckets.clone()).await;
+                info!(uuid = uuid_prefix(&uuid), "cleaned up expired connection");
             }
         }
-        
-        // 清理过期的连接
-        for uuid in expired_uuids {
-            println!("Cleaning up expired connection for UUID: {}", uuid);
-            ACTIVE_CONNECTIONS.remove(&uuid);
-            // 注意：这里我们不直接从缓存中移除，让moka自己处理
-            // 如果需要立即移除，可以调用 self.store.invalidate(&uuid).await;
-            // 对于 Tonic 后台连接信息的清理，需要在服务层实现特定的连接断开机制
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::num::NonZeroU32;
+
+    fn manager(expiry_threshold: Duration) -> GlobalStateManager {
+        GlobalStateManager::new(ConnectionConfig {
+            heartbeat_interval: expiry_threshold / 2,
+            expiry_threshold,
+            ..ConnectionConfig::default()
+        })
+    }
+
+    async fn available(manager: &GlobalStateManager, uuid: &str, service: &str) -> u32 {
+        let buckets = manager.buckets.get(uuid).await.unwrap();
+        buckets.get(service).unwrap().status().available
+    }
+
+    #[tokio::test]
+    async fn expired_uuid_is_removed_but_keeps_its_buckets() {
+        let manager = manager(Duration::from_millis(20));
+        let burst = RULE_REGISTRY.get("ankr").unwrap().burst();
+        manager.admit_request("expired-uuid", "192.0.2.1", "ankr").await.unwrap();
+        assert_eq!(available(&manager, "expired-uuid", "ankr").await, burst - 1);
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        manager.cleanup_expired_connections().await;
+        assert!(manager.store.get("expired-uuid").await.is_none());
+
+        // IP 绑定已释放，可从新 IP 重连；令牌桶沿用过期前的，不会重新获得满桶
+        manager.admit_request("expired-uuid", "192.0.2.2", "ankr").await.unwrap();
+        assert_eq!(available(&manager, "expired-uuid", "ankr").await, burst - 2);
+    }
+
+    #[tokio::test]
+    async fn active_uuid_survives_cleanup() {
+        let manager = manager(Duration::from_secs(60));
+        manager.admit_request("active-uuid", "192.0.2.1", "ankr").await.unwrap();
+
+        manager.cleanup_expired_connections().await;
+        let state = manager.store.get("active-uuid").await.unwrap();
+        assert!(state.is_connected());
+        assert_eq!(state.bound_ip.load_full().as_deref().map(String::as_str), Some("192.0.2.1"));
+    }
+
+    #[tokio::test]
+    async fn first_request_consumes_a_token_and_rejects_when_exhausted() {
+        let manager = manager(Duration::from_millis(20));
+        let burst = RULE_REGISTRY.get("ankr").unwrap().burst();
+        for _ in 0..burst {
+            manager.admit_request("reconnecting-uuid", "192.0.2.1", "ankr").await.unwrap();
         }
+        let status = manager
+            .admit_request("reconnecting-uuid", "192.0.2.1", "ankr")
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
     }
-    
-    // 检查连接是否仍然有效
-    pub async fn is_connection_valid(&self, uuid: &str) -> bool {
-        if let Some(state) = self.store.get(uuid).await {
-            state.is_connected()
-        } else {
-            false
+    #[tokio::test]
+    async fn bucket_status_decreases_after_consuming_and_recovers() {
+        let bucket = ServiceBucket::new(Quota::with_period(Duration::from_millis(200)).unwrap().allow_burst(NonZeroU32::new(3).unwrap()));
+        assert_eq!(bucket.status().available, 3);
+        assert_eq!(bucket.status().full_in, Duration::ZERO);
+
+        for expected in [2, 1, 0] {
+            assert!(bucket.check());
+            assert_eq!(bucket.status().available, expected);
         }
+
+        // 被拒绝的调用不能把状态重置为"刚刚耗尽"：首个令牌仍在 200ms 时到达
+        tokio::time::sleep(Duration::from_millis(120)).await;
+        assert!(!bucket.check());
+        assert_eq!(bucket.status().available, 0);
+        tokio::time::sleep(Duration::from_millis(120)).await;
+        assert_eq!(bucket.status().available, 1);
+        assert!(bucket.status().full_in > Duration::ZERO);
     }
-    
-    // 强制断开连接（包括清理 Tonic 后台连接信息）
-    // 注意：此函数需要与服务层配合使用，通过特定机制通知 Tonic 断开连接
-    pub async fn force_disconnect(&self, uuid: &str) {
-        if let Some(state) = self.store.get(uuid).await {
-            state.mark_disconnected();
-            ACTIVE_CONNECTIONS.remove(uuid);
-            // 这里可以添加与服务层通信的机制，通知 Tonic 断开特定连接
-            // 具体实现取决于服务层的设计
+
+    #[tokio::test]
+    async fn status_requires_the_bound_ip_and_never_creates_state() {
+        let manager = manager(Duration::from_secs(60));
+        let status = manager.rate_limit_status("status-uuid", "192.0.2.1", "ankr").await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::NotFound);
+        assert!(manager.store.get("status-uuid").await.is_none());
+        assert!(manager.buckets.get("status-uuid").await.is_none());
+
+        let burst = RULE_REGISTRY.get("ankr").unwrap().burst();
+        manager.admit_request("status-uuid", "192.0.2.1", "ankr").await.unwrap();
+        let status = manager.rate_limit_status("status-uuid", "192.0.2.9", "ankr").await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+
+        let status = manager.rate_limit_status("status-uuid", "192.0.2.1", "ankr").await.unwrap();
+        assert_eq!(status.available, burst - 1);
+        // 未使用过的服务报告满桶，且不会为其创建令牌桶
+        let burst = RULE_REGISTRY.get("metadata").unwrap().burst();
+        let status = manager.rate_limit_status("status-uuid", "192.0.2.1", "metadata").await.unwrap();
+        assert_eq!(status.available, burst);
+        assert!(manager.buckets.get("status-uuid").await.unwrap().get("metadata").is_none());
+    }
+    fn bound_ip(state: &ClientState) -> Option<String> {
+        state.bound_ip.load_full().as_deref().cloned()
+    }
+
+    #[test]
+    fn strict_policy_rejects_a_second_ip() {
+        let state = ClientState::new(Arc::default());
+        state.bind_ip("strict-uuid", "192.0.2.1", IpPolicy::Strict).unwrap();
+        state.bind_ip("strict-uuid", "192.0.2.1", IpPolicy::Strict).unwrap();
+        let status = state.bind_ip("strict-uuid", "192.0.2.2", IpPolicy::Strict).unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+        assert_eq!(bound_ip(&state).as_deref(), Some("192.0.2.1"));
+    }
+
+    #[test]
+    fn roaming_policy_rebinds_to_the_new_ip() {
+        let state = ClientState::new(Arc::default());
+        state.bind_ip("roaming-uuid", "192.0.2.1", IpPolicy::AllowRoaming).unwrap();
+        state.bind_ip("roaming-uuid", "192.0.2.2", IpPolicy::AllowRoaming).unwrap();
+        assert_eq!(bound_ip(&state).as_deref(), Some("192.0.2.2"));
+    }
+
+    #[test]
+    fn allow_n_policy_accepts_n_ips_and_rejects_the_next() {
+        let state = ClientState::new(Arc::default());
+        let policy = IpPolicy::AllowN { max_ips: 2, window: Duration::from_secs(60) };
+        state.bind_ip("allow-n-uuid", "192.0.2.1", policy).unwrap();
+        state.bind_ip("allow-n-uuid", "192.0.2.2", policy).unwrap();
+        // 已计入的 IP 可继续使用
+        state.bind_ip("allow-n-uuid", "192.0.2.1", policy).unwrap();
+        let status = state.bind_ip("allow-n-uuid", "192.0.2.3", policy).unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+        assert_eq!(bound_ip(&state).as_deref(), Some("192.0.2.1"));
+    }
+
+    #[tokio::test]
+    async fn rejected_ip_does_not_consume_the_bound_clients_tokens() {
+        let manager = manager(Duration::from_secs(60));
+        let burst = RULE_REGISTRY.get("ankr").unwrap().burst();
+        manager.admit_request("victim-uuid", "192.0.2.1", "ankr").await.unwrap();
+
+        for _ in 0..burst {
+            let status = manager.admit_request("victim-uuid", "198.51.100.7", "ankr").await.unwrap_err();
+            assert_eq!(status.code(), tonic::Code::PermissionDenied);
+        }
+        assert_eq!(available(&manager, "victim-uuid", "ankr").await, burst - 1);
+        manager.admit_request("victim-uuid", "192.0.2.1", "ankr").await.unwrap();
+        assert_eq!(available(&manager, "victim-uuid", "ankr").await, burst - 2);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_first_requests_bind_only_one_ip() {
+        let manager = Arc::new(manager(Duration::from_secs(60)));
+        let burst = RULE_REGISTRY.get("ankr").unwrap().burst();
+        let barrier = Arc::new(tokio::sync::Barrier::new(8));
+        let tasks: Vec<_> = (1..=8)
+            .map(|i| {
+                let (manager, barrier) = (manager.clone(), barrier.clone());
+                tokio::spawn(async move {
+                    let ip = format!("192.0.2.{i}");
+                    barrier.wait().await;
+                    manager.admit_request("racing-uuid", &ip, "ankr").await.map(|()| ip)
+                })
+            })
+            .collect();
+
+        let mut winners = Vec::new();
+        for task in tasks {
+            match task.await.unwrap() {
+                Ok(ip) => winners.push(ip),
+                Err(status) => assert_eq!(status.code(), tonic::Code::PermissionDenied),
+            }
+        }
+        assert_eq!(winners.len(), 1, "{winners:?}");
+        let state = manager.store.get("racing-uuid").await.unwrap();
+        assert_eq!(bound_ip(&state).as_ref(), Some(&winners[0]));
+        assert_eq!(available(&manager, "racing-uuid", "ankr").await, burst - 1);
+    }
+
+    #[tokio::test]
+    async fn store_is_bounded_by_max_clients() {
+        let manager = GlobalStateManager::new(ConnectionConfig {
+            max_clients: 4,
+            ..ConnectionConfig::default()
+        });
+        let evicted_before = capacity_evictions();
+        for i in 0..20 {
+            manager.admit_request(&format!("bounded-uuid-{i}"), "192.0.2.1", "ankr").await.unwrap();
+        }
+
+        manager.store.run_pending_tasks().await;
+        assert!(manager.store.entry_count() <= 4, "{}", manager.store.entry_count());
+        assert!(capacity_evictions() > evicted_before);
+    }
+
+    #[tokio::test]
+    async fn evicted_states_are_marked_disconnected() {
+        let manager = GlobalStateManager::new(ConnectionConfig {
+            max_clients: 2,
+            ..ConnectionConfig::default()
+        });
+        let mut states = Vec::new();
+        for i in 0..10 {
+            let uuid = format!("evicted-uuid-{i}");
+            manager.admit_request(&uuid, "192.0.2.1", "ankr").await.unwrap();
+            states.push((uuid.clone(), manager.store.get(&uuid).await.unwrap()));
         }
+        manager.store.run_pending_tasks().await;
+
+        let mut evicted = 0;
+        for (uuid, state) in &states {
+            if manager.store.get(uuid).await.is_none() {
+                assert!(!state.is_connected(), "{uuid}");
+                evicted += 1;
+            } else {
+                assert!(state.is_connected(), "{uuid}");
+            }
+        }
+        assert!(evicted > 0);
+
+        // 显式移除同样经过 eviction listener
+        let (uuid, state) = states.iter().find(|(_, state)| state.is_connected()).unwrap();
+        manager.store.invalidate(uuid).await;
+        manager.store.run_pending_tasks().await;
+        assert!(!state.is_connected());
+    }
+
+    #[test]
+    fn concurrent_last_active_updates_keep_expiry_consistent() {
+        let state = Arc::new(ClientState::new(Arc::new(DashMap::new())));
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let state = state.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..10_000 {
+                        state.update_last_active();
+                        assert!(!state.is_expired(Duration::from_secs(60)));
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let threshold = Duration::from_millis(50);
+        assert!(!state.is_expired(threshold));
+        std::thread::sleep(threshold * 2);
+        assert!(state.is_expired(threshold));
     }
-}
\ No newline at end of file
+
+    // 测试用日志缓冲：fmt layer 的输出写入共享 Vec
+    #[derive(Clone, Default)]
+    struct LogBuffer(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for LogBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn first_bind_logs_uuid_prefix_and_ip() {
+        let logs = LogBuffer::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .with_ansi(false)
+            .finish();
+
+        let state = ClientState::new(Arc::default());
+        tracing::subscriber::with_default(subscriber, || {
+            state.bind_ip("logged-uuid-0001", "192.0.2.1", IpPolicy::Strict).unwrap();
+            // 已绑定的同一 IP 不再记录
+            state.bind_ip("logged-uuid-0001", "192.0.2.1", IpPolicy::Strict).unwrap();
+        });
+
+        let output = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(output.matches("bound UUID to IP").count(), 1, "{output}");
+        assert!(output.contains("INFO"), "{output}");
+        assert!(output.contains("uuid=\"logged-u\""), "{output}");
+        assert!(output.contains("ip=192.0.2.1"), "{output}");
+        assert!(!output.contains("logged-uuid-0001"), "{output}");
+    }
+}
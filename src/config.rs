@@ -0,0 +1,866 @@
+// src/config.rs
+use crate::error::{AppError, Result};
+use crate::rules::{IpPolicy, QuotaWindow, ServiceRule, default_rate_rules};
+use crate::utils::{host_allowed, mask_secret};
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::env;
+use std::net::SocketAddr;
+use std::num::NonZeroU32;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// 全局配置：启动时从环境变量（及 .env）一次性加载并校验
+#[derive(Clone, Debug)]
+pub struct Config {
+    // Ankr 多链 API key 池，逗号分隔 (ANKR_API_KEYS，未设置时读取 ANKR_API_KEY)
+    pub ankr_api_keys: Vec<String>,
+    // 被上游限流 (429) 的 key 的冷却时间 (ANKR_KEY_COOLDOWN_SECS)
+    pub ankr_key_cooldown: Duration,
+    // Ankr 多链 API 基础地址，API key 追加在路径末尾；测试时可指向 mock (ANKR_MULTICHAIN_URL)
+    pub ankr_multichain_url: String,
+    // Postgres 连接串，为空表示不启用数据库 (DATABASE_URL)
+    pub database_url: String,
+    // gRPC 与 HTTPS 监听地址 (GRPC_ADDR / HTTP_ADDR)
+    pub grpc_addr: SocketAddr,
+    pub http_addr: SocketAddr,
+    // TLS 证书与私钥路径 (TLS_CERT_PATH / TLS_KEY_PATH)
+    pub tls_cert_path: String,
+    pub tls_key_path: String,
+    // gRPC 双向 TLS：客户端证书 CA 路径，为空表示不启用 (MTLS_CA_PATH)
+    pub mtls_ca_path: String,
+    // 启用 mTLS 时是否允许不带证书的客户端，回退到 UUID 标识 (MTLS_OPTIONAL)
+    pub mtls_optional: bool,
+    // 每个 HTTP/2 连接允许的最大并发流 (HTTP2_MAX_CONCURRENT_STREAMS)
+    pub http2_max_concurrent_streams: u32,
+    // HTTPS 端口 TLS 握手超时，防止只建 TCP 不发 ClientHello 的连接长期占用任务 (TLS_HANDSHAKE_TIMEOUT_SECS)
+    pub tls_handshake_timeout: Duration,
+    // HTTPS 端口最大并发连接数，超出后直接拒绝新连接 (HTTP_MAX_CONNECTIONS)
+    pub http_max_connections: usize,
+    // HTTPS 端口读取请求头的超时，防御慢速发送请求头的连接 (HTTP_HEADER_READ_TIMEOUT_SECS)
+    pub http_header_read_timeout: Duration,
+    // HTTPS 端口 HTTP/2 keep-alive ping 间隔，无响应的空闲连接会被关闭 (HTTP_SERVER_KEEPALIVE_SECS)
+    pub http_keepalive_interval: Duration,
+    // OTLP trace 导出地址，为空表示不导出 (OTEL_EXPORTER_OTLP_ENDPOINT)
+    pub otlp_endpoint: String,
+    // 是否注册 gRPC reflection 服务，生产环境可关闭 (GRPC_REFLECTION)
+    pub grpc_reflection: bool,
+    // 关闭时等待进行中请求完成的最长时间 (SHUTDOWN_DRAIN_TIMEOUT_SECS)
+    pub shutdown_drain_timeout: Duration,
+    // 收到关闭信号后先报告 NOT_SERVING，等待该时长再停止接收请求，便于负载均衡摘除实例 (SHUTDOWN_GRACE_SECS)
+    pub shutdown_grace_period: Duration,
+    // 审计事件写库队列容量 (AUTH_EVENT_QUEUE_SIZE)
+    pub auth_event_queue_size: usize,
+    // 访问日志写库队列容量与批量刷新间隔 (REQUEST_LOG_QUEUE_SIZE / REQUEST_LOG_FLUSH_SECS)
+    pub request_log_queue_size: usize,
+    pub request_log_flush_interval: Duration,
+    // 维护模式下返回给客户端的 Retry-After 秒数 (MAINTENANCE_RETRY_AFTER_SECS)
+    pub maintenance_retry_after: Duration,
+    // 每个调用方每月最多请求数，0 表示不限制（仍会计数）(MONTHLY_REQUEST_QUOTA)
+    pub monthly_request_quota: u64,
+    // 月度用量写库间隔 (USAGE_FLUSH_SECS)
+    pub usage_flush_interval: Duration,
+    // 内存中最多保留的调用方用量计数，超出后按 LRU 淘汰（淘汰前写库）(USAGE_MAX_IDENTITIES)
+    pub usage_max_identities: u64,
+    // 管理端点的 Bearer token，为空表示禁用 /debug/config (ADMIN_TOKEN)
+    pub admin_token: String,
+    // /metrics 与只读管理端点的 Bearer token (METRICS_TOKEN)
+    pub metrics_token: String,
+    // 同上，Basic 认证凭据，格式 user:password (METRICS_BASIC_AUTH)
+    pub metrics_basic_auth: String,
+    // 显式允许匿名访问 /metrics，默认 false (METRICS_ALLOW_UNAUTHENTICATED)
+    pub metrics_allow_unauthenticated: bool,
+    // 客户端 IP 黑名单文件（每行一个 CIDR），为空表示不启用 (IP_BLOCKLIST_PATH)
+    pub ip_blocklist_path: String,
+    // 检查黑名单文件变化并重新加载的间隔 (IP_BLOCKLIST_RELOAD_SECS)
+    pub ip_blocklist_reload_interval: Duration,
+    // 限流规则 TOML 文件，为空表示使用内置规则 (RATE_RULES_PATH)
+    pub rate_rules_path: String,
+    // 按服务名的限流规则，启动时写入 RULE_REGISTRY
+    pub rate_rules: HashMap<String, ServiceRule>,
+    pub connection: ConnectionConfig,
+    pub http_client: HttpClientConfig,
+    pub indexer: IndexerConfig,
+    pub log_sampling: LogSamplingConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            ankr_api_keys: Vec::new(),
+            ankr_key_cooldown: Duration::from_secs(60),
+            ankr_multichain_url: "https://rpc.ankr.com/multichain/".into(),
+            database_url: String::new(),
+            grpc_addr: SocketAddr::from(([0, 0, 0, 0], 50051)),
+            http_addr: SocketAddr::from(([0, 0, 0, 0], 8443)),
+            tls_cert_path: "./cert.pem".into(),
+            tls_key_path: "./key.pem".into(),
+            mtls_ca_path: String::new(),
+            mtls_optional: false,
+            http2_max_concurrent_streams: 256,
+            tls_handshake_timeout: Duration::from_secs(10),
+            http_max_connections: 1024,
+            http_header_read_timeout: Duration::from_secs(10),
+            http_keepalive_interval: Duration::from_secs(60),
+            otlp_endpoint: String::new(),
+            grpc_reflection: false,
+            shutdown_drain_timeout: Duration::from_secs(30),
+            shutdown_grace_period: Duration::from_secs(5),
+            auth_event_queue_size: 1024,
+            request_log_queue_size: 4096,
+            request_log_flush_interval: Duration::from_secs(5),
+            maintenance_retry_after: Duration::from_secs(60),
+            monthly_request_quota: 0,
+            usage_flush_interval: Duration::from_secs(30),
+            usage_max_identities: 100_000,
+            admin_token: String::new(),
+            metrics_token: String::new(),
+            metrics_basic_auth: String::new(),
+            metrics_allow_unauthenticated: false,
+            ip_blocklist_path: String::new(),
+            ip_blocklist_reload_interval: Duration::from_secs(60),
+            rate_rules_path: String::new(),
+            rate_rules: default_rate_rules(),
+            connection: ConnectionConfig::default(),
+            http_client: HttpClientConfig::default(),
+            indexer: IndexerConfig::default(),
+            log_sampling: LogSamplingConfig::default(),
+        }
+    }
+}
+
+impl Config {
+    pub fn from_env() -> Result<Self> {
+        dotenvy::dotenv().ok();
+        Self::from_vars(&|name| env::var(name).ok())
+    }
+
+    // 从任意变量来源加载（from_env 使用进程环境变量，测试可传入固定的变量表）
+    fn from_vars(vars: &Vars) -> Result<Self> {
+        let default = Self::default();
+        let rate_rules_path = env_string(vars, "RATE_RULES_PATH", &default.rate_rules_path);
+        let rate_rules = if rate_rules_path.is_empty() {
+            default.rate_rules
+        } else {
+            load_rate_rules(&rate_rules_path)?
+        };
+        let config = Self {
+            ankr_api_keys: env_list(vars, "ANKR_API_KEYS")
+                .or_else(|| env_list(vars, "ANKR_API_KEY"))
+                .unwrap_or(default.ankr_api_keys),
+            ankr_key_cooldown: env_secs(vars, "ANKR_KEY_COOLDOWN_SECS", default.ankr_key_cooldown)?,
+            ankr_multichain_url: with_trailing_slash(env_string(vars, "ANKR_MULTICHAIN_URL", &default.ankr_multichain_url)),
+            database_url: env_string(vars, "DATABASE_URL", &default.database_url),
+            grpc_addr: env_parse(vars, "GRPC_ADDR", default.grpc_addr)?,
+            http_addr: env_parse(vars, "HTTP_ADDR", default.http_addr)?,
+            tls_cert_path: env_string(vars, "TLS_CERT_PATH", &default.tls_cert_path),
+            tls_key_path: env_string(vars, "TLS_KEY_PATH", &default.tls_key_path),
+            mtls_ca_path: env_string(vars, "MTLS_CA_PATH", &default.mtls_ca_path),
+            mtls_optional: env_parse(vars, "MTLS_OPTIONAL", default.mtls_optional)?,
+            http2_max_concurrent_streams: env_parse(vars, "HTTP2_MAX_CONCURRENT_STREAMS", default.http2_max_concurrent_streams)?,
+            tls_handshake_timeout: env_secs(vars, "TLS_HANDSHAKE_TIMEOUT_SECS", default.tls_handshake_timeout)?,
+            http_max_connections: env_parse(vars, "HTTP_MAX_CONNECTIONS", default.http_max_connections)?,
+            http_header_read_timeout: env_secs(vars, "HTTP_HEADER_READ_TIMEOUT_SECS", default.http_header_read_timeout)?,
+            http_keepalive_interval: env_secs(vars, "HTTP_SERVER_KEEPALIVE_SECS", default.http_keepalive_interval)?,
+            otlp_endpoint: env_string(vars, "OTEL_EXPORTER_OTLP_ENDPOINT", &default.otlp_endpoint),
+            grpc_reflection: env_parse(vars, "GRPC_REFLECTION", default.grpc_reflection)?,
+            shutdown_drain_timeout: env_secs(vars, "SHUTDOWN_DRAIN_TIMEOUT_SECS", default.shutdown_drain_timeout)?,
+            shutdown_grace_period: env_secs(vars, "SHUTDOWN_GRACE_SECS", default.shutdown_grace_period)?,
+            auth_event_queue_size: env_parse(vars, "AUTH_EVENT_QUEUE_SIZE", default.auth_event_queue_size)?,
+            request_log_queue_size: env_parse(vars, "REQUEST_LOG_QUEUE_SIZE", default.request_log_queue_size)?,
+            request_log_flush_interval: env_secs(vars, "REQUEST_LOG_FLUSH_SECS", default.request_log_flush_interval)?,
+            maintenance_retry_after: env_secs(vars, "MAINTENANCE_RETRY_AFTER_SECS", default.maintenance_retry_after)?,
+            monthly_request_quota: env_parse(vars, "MONTHLY_REQUEST_QUOTA", default.monthly_request_quota)?,
+            usage_flush_interval: env_secs(vars, "USAGE_FLUSH_SECS", default.usage_flush_interval)?,
+            usage_max_identities: env_parse(vars, "USAGE_MAX_IDENTITIES", default.usage_max_identities)?,
+            admin_token: env_string(vars, "ADMIN_TOKEN", &default.admin_token),
+            metrics_token: env_string(vars, "METRICS_TOKEN", &default.metrics_token),
+            metrics_basic_auth: env_string(vars, "METRICS_BASIC_AUTH", &default.metrics_basic_auth),
+            metrics_allow_unauthenticated: env_parse(vars, "METRICS_ALLOW_UNAUTHENTICATED", default.metrics_allow_unauthenticated)?,
+            ip_blocklist_path: env_string(vars, "IP_BLOCKLIST_PATH", &default.ip_blocklist_path),
+            ip_blocklist_reload_interval: env_secs(vars, "IP_BLOCKLIST_RELOAD_SECS", default.ip_blocklist_reload_interval)?,
+            rate_rules_path,
+            rate_rules,
+            connection: ConnectionConfig::from_vars(vars)?,
+            http_client: HttpClientConfig::from_vars(vars)?,
+            indexer: IndexerConfig::from_vars(vars)?,
+            log_sampling: LogSamplingConfig::from_vars(vars)?,
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.grpc_addr == self.http_addr {
+            return Err(AppError::Custom("GRPC_ADDR and HTTP_ADDR must differ".into()));
+        }
+        if self.http2_max_concurrent_streams == 0 {
+            return Err(AppError::Custom("HTTP2_MAX_CONCURRENT_STREAMS must be > 0".into()));
+        }
+        if self.auth_event_queue_size == 0 {
+            return Err(AppError::Custom("AUTH_EVENT_QUEUE_SIZE must be > 0".into()));
+        }
+        if self.http_max_connections == 0 {
+            return Err(AppError::Custom("HTTP_MAX_CONNECTIONS must be > 0".into()));
+        }
+        if self.tls_handshake_timeout.is_zero()
+            || self.http_header_read_timeout.is_zero()
+            || self.http_keepalive_interval.is_zero()
+        {
+            return Err(AppError::Custom(
+                "TLS_HANDSHAKE_TIMEOUT_SECS, HTTP_HEADER_READ_TIMEOUT_SECS and HTTP_SERVER_KEEPALIVE_SECS must be > 0"
+                    .into(),
+            ));
+        }
+        if self.ip_blocklist_reload_interval.is_zero() {
+            return Err(AppError::Custom("IP_BLOCKLIST_RELOAD_SECS must be > 0".into()));
+        }
+        if self.usage_flush_interval.is_zero() {
+            return Err(AppError::Custom("USAGE_FLUSH_SECS must be > 0".into()));
+        }
+        if self.usage_max_identities == 0 {
+            return Err(AppError::Custom("USAGE_MAX_IDENTITIES must be > 0".into()));
+        }
+        if self.request_log_queue_size == 0 || self.request_log_flush_interval.is_zero() {
+            return Err(AppError::Custom(
+                "REQUEST_LOG_QUEUE_SIZE and REQUEST_LOG_FLUSH_SECS must be > 0".into(),
+            ));
+        }
+        // 基础地址须为 http(s) 且 host 在上游白名单内，否则所有索引请求都会被拦截
+        let ankr_url_valid = reqwest::Url::parse(&self.ankr_multichain_url).is_ok_and(|url| {
+            matches!(url.scheme(), "http" | "https")
+                && url
+                    .host_str()
+                    .is_some_and(|host| host_allowed(&host.to_ascii_lowercase(), &self.http_client.allowed_hosts))
+        });
+        if !ankr_url_valid {
+            return Err(AppError::Custom(
+                "ANKR_MULTICHAIN_URL must be an http(s) URL whose host is in UPSTREAM_ALLOWED_HOSTS".into(),
+            ));
+        }
+        if !self.metrics_basic_auth.is_empty() && !self.metrics_basic_auth.contains(':') {
+            return Err(AppError::Custom("METRICS_BASIC_AUTH must be in user:password form".into()));
+        }
+        // gRPC 索引服务按 ankr 规则限流
+        if !self.rate_rules.contains_key("ankr") {
+            return Err(AppError::Custom("rate rules must define the \"ankr\" service".into()));
+        }
+        if let Some(service) = self.connection.ip_policies.keys().find(|s| !self.rate_rules.contains_key(*s)) {
+            return Err(AppError::Custom(format!("IP_POLICIES: unknown service {service}")));
+        }
+        self.connection.validate()?;
+        self.http_client.validate()?;
+        self.indexer.validate()?;
+        self.log_sampling.validate()?;
+        Ok(())
+    }
+
+    /// 生效配置的 JSON 视图，所有密钥与连接串均已遮蔽，供 /debug/config 使用
+    pub fn redacted_json(&self) -> Value {
+        let secs = |d: Duration| d.as_secs();
+        let mut json = serde_json::json!({
+            "ankr_api_keys": self.ankr_api_keys.iter().map(|k| mask_secret(k)).collect::<Vec<_>>(),
+            "ankr_key_cooldown_secs": secs(self.ankr_key_cooldown),
+            "ankr_multichain_url": self.ankr_multichain_url,
+            "database_url": mask_secret(&self.database_url),
+            "grpc_addr": self.grpc_addr.to_string(),
+            "http_addr": self.http_addr.to_string(),
+            "tls_cert_path": self.tls_cert_path,
+            "tls_key_path": self.tls_key_path,
+            "mtls_ca_path": self.mtls_ca_path,
+            "mtls_optional": self.mtls_optional,
+            "http2_max_concurrent_streams": self.http2_max_concurrent_streams,
+            "tls_handshake_timeout_secs": secs(self.tls_handshake_timeout),
+            "http_max_connections": self.http_max_connections,
+            "http_header_read_timeout_secs": secs(self.http_header_read_timeout),
+            "http_server_keepalive_secs": secs(self.http_keepalive_interval),
+            "otlp_endpoint": self.otlp_endpoint,
+            "grpc_reflection": self.grpc_reflection,
+            "shutdown_drain_timeout_secs": secs(self.shutdown_drain_timeout),
+            "shutdown_grace_secs": secs(self.shutdown_grace_period),
+            "auth_event_queue_size": self.auth_event_queue_size,
+            "request_log_queue_size": self.request_log_queue_size,
+            "request_log_flush_secs": secs(self.request_log_flush_interval),
+            "maintenance_retry_after_secs": secs(self.maintenance_retry_after),
+            "monthly_request_quota": self.monthly_request_quota,
+            "usage_flush_secs": secs(self.usage_flush_interval),
+            "usage_max_identities": self.usage_max_identities,
+            "admin_token": mask_secret(&self.admin_token),
+            "metrics_token": mask_secret(&self.metrics_token),
+            // 只显示用户名
+            "metrics_basic_auth": self
+                .metrics_basic_auth
+                .split_once(':')
+                .map(|(user, _)| format!("{}:***", user))
+                .unwrap_or_default(),
+            "metrics_allow_unauthenticated": self.metrics_allow_unauthenticated,
+            "ip_blocklist_path": self.ip_blocklist_path,
+            "ip_blocklist_reload_secs": secs(self.ip_blocklist_reload_interval),
+            "connection": {
+                "heartbeat_interval_secs": secs(self.connection.heartbeat_interval),
+                "expiry_threshold_secs": secs(self.connection.expiry_threshold),
+                "idle_ttl_secs": secs(self.connection.idle_ttl),
+                "max_clients": self.connection.max_clients,
+                "ip_policies": self
+                    .connection
+                    .ip_policies
+                    .iter()
+                    .map(|(service, policy)| (service.clone(), policy.to_string()))
+                    .collect::<HashMap<_, _>>(),
+            },
+            "http_client": {
+                "timeout_secs": secs(self.http_client.timeout),
+                "connect_timeout_secs": secs(self.http_client.connect_timeout),
+                "pool_max_idle_per_host": self.http_client.pool_max_idle_per_host,
+                "http2_keep_alive_timeout_secs": secs(self.http_client.http2_keep_alive_timeout),
+                "max_concurrency_per_host": self.http_client.max_concurrency_per_host,
+                "acquire_timeout_secs": secs(self.http_client.acquire_timeout),
+                "allowed_hosts": self.http_client.allowed_hosts,
+            },
+            "indexer": {
+                "tx_history_cap": self.indexer.tx_history_cap,
+                "asset_cap": self.indexer.asset_cap,
+                "max_addresses": self.indexer.max_addresses,
+                "cache_ttl_secs": secs(self.indexer.cache_ttl),
+                "cache_max_entries": self.indexer.cache_max_entries,
+                "strict_checksum": self.indexer.strict_checksum,
+            },
+            "log_sampling": {
+                "default_rate": self.log_sampling.default_rate,
+                "routes": self.log_sampling.routes,
+            },
+        });
+        // 单个 json! 宏的 key 数量已接近递归上限，限流规则单独写入
+        let rate_rules: HashMap<_, _> = self
+            .rate_rules
+            .iter()
+            .map(|(service, rule)| {
+                let rule = serde_json::json!({
+                    "quota": rule.to_string(),
+                    "stream_limit": rule.stream_limit,
+                    "ip_policy": rule.ip_policy.to_string(),
+                });
+                (service.clone(), rule)
+            })
+            .collect();
+        json["rate_rules_path"] = self.rate_rules_path.clone().into();
+        json["rate_rules"] = serde_json::json!(rate_rules);
+        json
+    }
+}
+
+/// 连接生命周期配置：心跳间隔、连接过期阈值、客户端状态闲置回收时间
+#[derive(Clone, Debug)]
+pub struct ConnectionConfig {
+    // 心跳检测间隔 (HEARTBEAT_INTERVAL_SECS)
+    pub heartbeat_interval: Duration,
+    // 超过该时长无活动视为连接过期 (CONN_EXPIRY_SECS)
+    pub expiry_threshold: Duration,
+    // moka 缓存中客户端状态的 idle 回收时间 (CLIENT_IDLE_TTL_SECS)
+    pub idle_ttl: Duration,
+    // 内存中最多保留的客户端状态数，超出后按 LRU 淘汰 (CLIENT_MAX_STATES)
+    pub max_clients: u64,
+    // 按服务覆盖 UUID 的 IP 绑定策略，格式 "ankr=allow_roaming,metadata=allow_n:2:3600" (IP_POLICIES)
+    pub ip_policies: HashMap<String, IpPolicy>,
+}
+
+impl Default for ConnectionConfig {
+    fn default() -> Self {
+        Self {
+            heartbeat_interval: Duration::from_secs(30),
+            expiry_threshold: Duration::from_secs(60),
+            idle_ttl: Duration::from_secs(600),
+            max_clients: 100_000,
+            ip_policies: HashMap::new(),
+        }
+    }
+}
+
+impl ConnectionConfig {
+    fn from_vars(vars: &Vars) -> Result<Self> {
+        let default = Self::default();
+        let mut ip_policies = HashMap::new();
+        for item in env_list(vars, "IP_POLICIES").unwrap_or_default() {
+            let (service, policy) = item.split_once('=').ok_or_else(|| {
+                AppError::Custom(format!("invalid value for IP_POLICIES: {item}"))
+            })?;
+            let policy = policy.parse().map_err(|e| {
+                AppError::Custom(format!("invalid value for IP_POLICIES: {item}: {e}"))
+            })?;
+            ip_policies.insert(service.trim().to_string(), policy);
+        }
+        let config = Self {
+            heartbeat_interval: env_secs(vars, "HEARTBEAT_INTERVAL_SECS", default.heartbeat_interval)?,
+            expiry_threshold: env_secs(vars, "CONN_EXPIRY_SECS", default.expiry_threshold)?,
+            idle_ttl: env_secs(vars, "CLIENT_IDLE_TTL_SECS", default.idle_ttl)?,
+            max_clients: env_parse(vars, "CLIENT_MAX_STATES", default.max_clients)?,
+            ip_policies,
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    // 心跳间隔 < 过期阈值 <= 闲置回收时间，否则过期检测没有意义
+    pub fn validate(&self) -> Result<()> {
+        if self.max_clients == 0 {
+            return Err(AppError::Custom("CLIENT_MAX_STATES must be > 0".into()));
+        }
+        if self.heartbeat_interval.is_zero() {
+            return Err(AppError::Custom("HEARTBEAT_INTERVAL_SECS must be > 0".into()));
+        }
+        if self.expiry_threshold <= self.heartbeat_interval {
+            return Err(AppError::Custom(
+                "CONN_EXPIRY_SECS must be greater than HEARTBEAT_INTERVAL_SECS".into(),
+            ));
+        }
+        if self.idle_ttl < self.expiry_threshold {
+            return Err(AppError::Custom(
+                "CLIENT_IDLE_TTL_SECS must not be less than CONN_EXPIRY_SECS".into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// 上游 HTTP 客户端配置
+#[derive(Clone, Debug)]
+pub struct HttpClientConfig {
+    // 整个请求的超时时间 (HTTP_TIMEOUT_SECS)
+    pub timeout: Duration,
+    // 建立连接的超时时间，上游不可达时快速失败 (HTTP_CONNECT_TIMEOUT_SECS)
+    pub connect_timeout: Duration,
+    // 每个 host 的最大空闲连接数 (HTTP_POOL_MAX_IDLE)
+    pub pool_max_idle_per_host: usize,
+    // HTTP/2 keep-alive ping 超时 (HTTP2_KEEPALIVE_SECS)
+    pub http2_keep_alive_timeout: Duration,
+    // 每个上游 host 的最大并发请求数 (UPSTREAM_MAX_CONCURRENCY)
+    pub max_concurrency_per_host: usize,
+    // 等待并发许可的最长时间，超时返回 resource_exhausted (UPSTREAM_ACQUIRE_TIMEOUT_SECS)
+    pub acquire_timeout: Duration,
+    // 允许访问的上游域名，子域名同样放行 (UPSTREAM_ALLOWED_HOSTS，逗号分隔)
+    pub allowed_hosts: Vec<String>,
+}
+
+impl Default for HttpClientConfig {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(10),
+            connect_timeout: Duration::from_secs(3),
+            pool_max_idle_per_host: 10,
+            http2_keep_alive_timeout: Duration::from_secs(30),
+            max_concurrency_per_host: 32,
+            acquire_timeout: Duration::from_secs(5),
+            allowed_hosts: vec!["ankr.com".into(), "blastapi.io".into()],
+        }
+    }
+}
+
+impl HttpClientConfig {
+    fn from_vars(vars: &Vars) -> Result<Self> {
+        let default = Self::default();
+        let config = Self {
+            timeout: env_secs(vars, "HTTP_TIMEOUT_SECS", default.timeout)?,
+            connect_timeout: env_secs(vars, "HTTP_CONNECT_TIMEOUT_SECS", default.connect_timeout)?,
+            pool_max_idle_per_host: env_parse(vars, "HTTP_POOL_MAX_IDLE", default.pool_max_idle_per_host)?,
+            http2_keep_alive_timeout: env_secs(vars, "HTTP2_KEEPALIVE_SECS", default.http2_keep_alive_timeout)?,
+            max_concurrency_per_host: env_parse(vars, "UPSTREAM_MAX_CONCURRENCY", default.max_concurrency_per_host)?,
+            acquire_timeout: env_secs(vars, "UPSTREAM_ACQUIRE_TIMEOUT_SECS", default.acquire_timeout)?,
+            allowed_hosts: env_list(vars, "UPSTREAM_ALLOWED_HOSTS")
+                .map(|hosts| hosts.into_iter().map(|h| h.to_ascii_lowercase()).collect())
+                .unwrap_or(default.allowed_hosts),
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.timeout.is_zero() {
+            return Err(AppError::Custom("HTTP_TIMEOUT_SECS must be > 0".into()));
+        }
+        if self.connect_timeout.is_zero() || self.connect_timeout > self.timeout {
+            return Err(AppError::Custom(
+                "HTTP_CONNECT_TIMEOUT_SECS must be > 0 and not exceed HTTP_TIMEOUT_SECS".into(),
+            ));
+        }
+        if self.max_concurrency_per_host == 0 {
+            return Err(AppError::Custom("UPSTREAM_MAX_CONCURRENCY must be > 0".into()));
+        }
+        if self.allowed_hosts.is_empty() {
+            return Err(AppError::Custom("UPSTREAM_ALLOWED_HOSTS must not be empty".into()));
+        }
+        Ok(())
+    }
+
+    pub fn build_client(&self) -> Result<Client> {
+        let client = Client::builder()
+            .use_rustls_tls()
+            .pool_max_idle_per_host(self.pool_max_idle_per_host)
+            .http2_keep_alive_timeout(self.http2_keep_alive_timeout)
+            .timeout(self.timeout)
+            .connect_timeout(self.connect_timeout)
+            .gzip(true)
+            .brotli(true)
+            .build()?;
+        Ok(client)
+    }
+}
+
+/// 索引服务配置：单次请求最多拉取的条目数，超出后返回上游 page token 供客户端续拉
+#[derive(Clone, Debug)]
+pub struct IndexerConfig {
+    // 交易历史单次最多条目数 (INDEXER_TX_HISTORY_CAP)
+    pub tx_history_cap: usize,
+    // 余额 / NFT 单次最多条目数 (INDEXER_ASSET_CAP)
+    pub asset_cap: usize,
+    // 单次请求允许的最大地址数 (INDEXER_MAX_ADDRESSES)
+    pub max_addresses: usize,
+    // 查询结果缓存 TTL，0 表示不缓存 (INDEXER_CACHE_TTL_SECS)
+    pub cache_ttl: Duration,
+    // 每类查询最多缓存的条目数 (INDEXER_CACHE_MAX_ENTRIES)
+    pub cache_max_entries: u64,
+    // 大小写混合的地址是否强制校验 EIP-55 校验和 (INDEXER_STRICT_CHECKSUM)
+    pub strict_checksum: bool,
+}
+
+impl Default for IndexerConfig {
+    fn default() -> Self {
+        Self {
+            tx_history_cap: 10_000,
+            asset_cap: 1000,
+            max_addresses: 20,
+            cache_ttl: Duration::from_secs(30),
+            cache_max_entries: 10_000,
+            strict_checksum: false,
+        }
+    }
+}
+
+impl IndexerConfig {
+    fn from_vars(vars: &Vars) -> Result<Self> {
+        let default = Self::default();
+        let config = Self {
+            tx_history_cap: env_parse(vars, "INDEXER_TX_HISTORY_CAP", default.tx_history_cap)?,
+            asset_cap: env_parse(vars, "INDEXER_ASSET_CAP", default.asset_cap)?,
+            max_addresses: env_parse(vars, "INDEXER_MAX_ADDRESSES", default.max_addresses)?,
+            cache_ttl: env_secs(vars, "INDEXER_CACHE_TTL_SECS", default.cache_ttl)?,
+            cache_max_entries: env_parse(vars, "INDEXER_CACHE_MAX_ENTRIES", default.cache_max_entries)?,
+            strict_checksum: env_parse(vars, "INDEXER_STRICT_CHECKSUM", default.strict_checksum)?,
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.tx_history_cap == 0 || self.asset_cap == 0 || self.max_addresses == 0 {
+            return Err(AppError::Custom(
+                "INDEXER_TX_HISTORY_CAP, INDEXER_ASSET_CAP and INDEXER_MAX_ADDRESSES must be > 0"
+                    .into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// 请求日志采样：成功请求每 N 条记录一条，失败请求总是记录
+#[derive(Clone, Debug)]
+pub struct LogSamplingConfig {
+    // 默认采样率，1 表示全部记录 (LOG_SAMPLE_RATE)
+    pub default_rate: u64,
+    // 按路由覆盖采样率，格式 "/health=100,get_asset_balance=10" (LOG_SAMPLE_ROUTES)
+    pub routes: HashMap<String, u64>,
+}
+
+impl Default for LogSamplingConfig {
+    fn default() -> Self {
+        Self {
+            default_rate: 1,
+            routes: HashMap::new(),
+        }
+    }
+}
+
+impl LogSamplingConfig {
+    fn from_vars(vars: &Vars) -> Result<Self> {
+        let default = Self::default();
+        let mut routes = HashMap::new();
+        for item in env_list(vars, "LOG_SAMPLE_ROUTES").unwrap_or_default() {
+            let (route, rate) = item.split_once('=').ok_or_else(|| {
+                AppError::Custom(format!("invalid value for LOG_SAMPLE_ROUTES: {item}"))
+            })?;
+            let rate = rate.trim().parse().map_err(|e| {
+                AppError::Custom(format!("invalid value for LOG_SAMPLE_ROUTES: {item}: {e}"))
+            })?;
+            routes.insert(route.trim().to_string(), rate);
+        }
+        let config = Self {
+            default_rate: env_parse(vars, "LOG_SAMPLE_RATE", default.default_rate)?,
+            routes,
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.default_rate == 0 || self.routes.values().any(|&rate| rate == 0) {
+            return Err(AppError::Custom(
+                "LOG_SAMPLE_RATE and LOG_SAMPLE_ROUTES rates must be > 0".into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+// 限流规则文件中的一条规则，除 ip_policy 外均为必填
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RateRuleEntry {
+    count: NonZeroU32,
+    window: String,
+    burst: NonZeroU32,
+    stream_limit: u64,
+    ip_policy: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RateRulesFile {
+    rules: HashMap<String, RateRuleEntry>,
+}
+
+// 读取 RATE_RULES_PATH 指向的 TOML 文件
+fn load_rate_rules(path: &str) -> Result<HashMap<String, ServiceRule>> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| AppError::Custom(format!("failed to read RATE_RULES_PATH {path}: {e}")))?;
+    parse_rate_rules(&text).map_err(|e| AppError::Custom(format!("invalid rate rules in {path}: {e}")))
+}
+
+// 解析限流规则，例如：
+// [rules.ankr]
+// count = 10
+// window = "hour"
+// burst = 3
+// stream_limit = 50
+// ip_policy = "allow_n:2:3600"
+fn parse_rate_rules(text: &str) -> std::result::Result<HashMap<String, ServiceRule>, String> {
+    let file: RateRulesFile = toml::from_str(text).map_err(|e| e.message().to_string())?;
+    file.rules
+        .into_iter()
+        .map(|(service, entry)| {
+            let window: QuotaWindow = entry.window.parse().map_err(|e| format!("rules.{service}.window: {e}"))?;
+            let mut rule = ServiceRule::new(entry.count, window, entry.burst, entry.stream_limit);
+            if let Some(policy) = entry.ip_policy {
+                rule.ip_policy = policy.parse().map_err(|e| format!("rules.{service}.ip_policy: {e}"))?;
+            }
+            Ok((service, rule))
+        })
+        .collect()
+}
+
+// 基础地址统一以 / 结尾，保证 key 拼接为独立的路径段
+fn with_trailing_slash(mut url: String) -> String {
+    if !url.ends_with('/') {
+        url.push('/');
+    }
+    url
+}
+
+// 变量来源：按名称读取，未设置时返回 None
+type Vars<'a> = dyn Fn(&str) -> Option<String> + 'a;
+
+// 读取字符串环境变量，未设置时使用默认值
+fn env_string(vars: &Vars, name: &str, default: &str) -> String {
+    vars(name).unwrap_or_else(|| default.to_string())
+}
+
+// 读取逗号分隔的列表，忽略空项；未设置或全为空时返回 None
+fn env_list(vars: &Vars, name: &str) -> Option<Vec<String>> {
+    let items: Vec<String> = vars(name)?
+        .split(',')
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect();
+    (!items.is_empty()).then_some(items)
+}
+
+// 读取并解析环境变量，未设置时使用默认值；解析失败时报错并指明变量名
+fn env_parse<T>(vars: &Vars, name: &str, default: T) -> Result<T>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    match vars(name) {
+        Some(v) => v
+            .trim()
+            .parse()
+            .map_err(|e| AppError::Custom(format!("invalid value for {name}: {e}"))),
+        None => Ok(default),
+    }
+}
+
+// 读取以秒为单位的环境变量，未设置时使用默认值
+fn env_secs(vars: &Vars, name: &str, default: Duration) -> Result<Duration> {
+    env_parse(vars, name, default.as_secs()).map(Duration::from_secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    const RULES_TOML: &str = r#"
+[rules.ankr]
+count = 30
+window = "minute"
+burst = 6
+stream_limit = 10
+ip_policy = "allow_n:2:3600"
+
+[rules.metadata]
+count = 100
+window = "hour"
+burst = 20
+stream_limit = 5
+"#;
+
+    fn rules_file(contents: &str) -> tempfile::NamedTempFile {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(contents.as_bytes()).unwrap();
+        file
+    }
+
+    fn load(vars: &[(&str, &str)]) -> Result<Config> {
+        let vars: HashMap<String, String> =
+            vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        Config::from_vars(&|name| vars.get(name).cloned())
+    }
+
+    fn error_message(result: Result<Config>) -> String {
+        match result {
+            Ok(_) => panic!("config should have been rejected"),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn fully_populated_config_loads_every_value() {
+        let rules = rules_file(RULES_TOML);
+        let config = load(&[
+            ("ANKR_API_KEYS", "key-a, key-b"),
+            ("ANKR_KEY_COOLDOWN_SECS", "90"),
+            ("ANKR_MULTICHAIN_URL", "https://rpc.ankr.com/multichain"),
+            ("DATABASE_URL", "postgres://gateway:secret@db/gateway"),
+            ("GRPC_ADDR", "127.0.0.1:50052"),
+            ("HTTP_ADDR", "127.0.0.1:8444"),
+            ("TLS_CERT_PATH", "/etc/gateway/cert.pem"),
+            ("TLS_KEY_PATH", "/etc/gateway/key.pem"),
+            ("MTLS_CA_PATH", "/etc/gateway/ca.pem"),
+            ("MTLS_OPTIONAL", "true"),
+            ("HTTP2_MAX_CONCURRENT_STREAMS", "128"),
+            ("SHUTDOWN_GRACE_SECS", "2"),
+            ("MONTHLY_REQUEST_QUOTA", "5000"),
+            ("USAGE_MAX_IDENTITIES", "500"),
+            ("ADMIN_TOKEN", "admin-token"),
+            ("METRICS_BASIC_AUTH", "prom:password"),
+            ("RATE_RULES_PATH", rules.path().to_str().unwrap()),
+            ("HEARTBEAT_INTERVAL_SECS", "10"),
+            ("CONN_EXPIRY_SECS", "20"),
+            ("CLIENT_IDLE_TTL_SECS", "300"),
+            ("IP_POLICIES", "metadata=allow_roaming"),
+            ("HTTP_TIMEOUT_SECS", "15"),
+            ("UPSTREAM_ALLOWED_HOSTS", "Ankr.com"),
+            ("INDEXER_TX_HISTORY_CAP", "2000"),
+            ("INDEXER_STRICT_CHECKSUM", "true"),
+            ("LOG_SAMPLE_RATE", "10"),
+            ("LOG_SAMPLE_ROUTES", "/health=100"),
+        ])
+        .unwrap();
+
+        assert_eq!(config.ankr_api_keys, ["key-a", "key-b"]);
+        assert_eq!(config.ankr_key_cooldown, Duration::from_secs(90));
+        assert_eq!(config.ankr_multichain_url, "https://rpc.ankr.com/multichain/");
+        assert_eq!(config.grpc_addr, "127.0.0.1:50052".parse().unwrap());
+        assert_eq!(config.http_addr, "127.0.0.1:8444".parse().unwrap());
+        assert_eq!(config.mtls_ca_path, "/etc/gateway/ca.pem");
+        assert!(config.mtls_optional);
+        assert_eq!(config.http2_max_concurrent_streams, 128);
+        assert_eq!(config.shutdown_grace_period, Duration::from_secs(2));
+        assert_eq!(config.monthly_request_quota, 5000);
+        assert_eq!(config.usage_max_identities, 500);
+        assert_eq!(config.connection.expiry_threshold, Duration::from_secs(20));
+        assert_eq!(config.connection.ip_policies["metadata"], IpPolicy::AllowRoaming);
+        assert_eq!(config.http_client.timeout, Duration::from_secs(15));
+        assert_eq!(config.http_client.allowed_hosts, ["ankr.com"]);
+        assert_eq!(config.indexer.tx_history_cap, 2000);
+        assert!(config.indexer.strict_checksum);
+        assert_eq!(config.log_sampling.routes["/health"], 100);
+
+        // 规则文件整体替换内置规则
+        assert_eq!(config.rate_rules.len(), 2);
+        let ankr = &config.rate_rules["ankr"];
+        assert_eq!((ankr.count, ankr.window, ankr.burst(), ankr.stream_limit), (30, QuotaWindow::Minute, 6, 10));
+        assert_eq!(ankr.ip_policy, IpPolicy::AllowN { max_ips: 2, window: Duration::from_secs(3600) });
+        assert_eq!(config.rate_rules["metadata"].ip_policy, IpPolicy::Strict);
+
+        // 密钥与连接串不会出现在 /debug/config 中
+        let json = config.redacted_json().to_string();
+        for secret in ["key-a", "secret@db", "admin-token", "password"] {
+            assert!(!json.contains(secret), "{secret} leaked into redacted config");
+        }
+        assert_eq!(config.redacted_json()["rate_rules"]["ankr"]["quota"], "30/minute, burst 6");
+    }
+
+    #[test]
+    fn defaults_apply_when_nothing_is_set() {
+        let config = load(&[]).unwrap();
+        assert!(config.ankr_api_keys.is_empty());
+        assert_eq!(config.grpc_addr, Config::default().grpc_addr);
+        assert_eq!(config.rate_rules.len(), default_rate_rules().len());
+    }
+
+    #[test]
+    fn rule_missing_a_required_field_is_rejected() {
+        for field in ["count", "window", "burst", "stream_limit"] {
+            let toml: String = RULES_TOML
+                .lines()
+                .filter(|line| !line.starts_with(&format!("{field} =")))
+                .collect::<Vec<_>>()
+                .join("\n");
+            let err = parse_rate_rules(&toml).unwrap_err();
+            assert!(err.contains(&format!("missing field `{field}`")), "{err}");
+
+            let file = rules_file(&toml);
+            let err = error_message(load(&[("RATE_RULES_PATH", file.path().to_str().unwrap())]));
+            assert!(err.contains("invalid rate rules"), "{err}");
+        }
+    }
+
+    #[test]
+    fn invalid_rate_rules_are_rejected() {
+        let bad_window = RULES_TOML.replace(r#""minute""#, r#""day""#);
+        assert!(parse_rate_rules(&bad_window).unwrap_err().contains("rules.ankr.window"));
+        let bad_policy = RULES_TOML.replace("allow_n:2:3600", "sometimes");
+        assert!(parse_rate_rules(&bad_policy).unwrap_err().contains("rules.ankr.ip_policy"));
+        let zero_burst = RULES_TOML.replace("burst = 6", "burst = 0");
+        assert!(parse_rate_rules(&zero_burst).is_err());
+
+        // 规则文件必须包含 gRPC 索引服务使用的 ankr 规则
+        let without_ankr = rules_file("[rules.metadata]\ncount = 1\nwindow = \"hour\"\nburst = 1\nstream_limit = 1\n");
+        let err = error_message(load(&[("RATE_RULES_PATH", without_ankr.path().to_str().unwrap())]));
+        assert!(err.contains("\"ankr\""), "{err}");
+
+        let err = error_message(load(&[("RATE_RULES_PATH", "/nonexistent/rules.toml")]));
+        assert!(err.contains("failed to read RATE_RULES_PATH"), "{err}");
+    }
+
+    #[test]
+    fn invalid_values_name_the_variable() {
+        let err = error_message(load(&[("GRPC_ADDR", "not-an-address")]));
+        assert!(err.contains("GRPC_ADDR"), "{err}");
+        let err = error_message(load(&[("IP_POLICIES", "unknown=strict")]));
+        assert!(err.contains("unknown service unknown"), "{err}");
+        let err = error_message(load(&[("USAGE_MAX_IDENTITIES", "0")]));
+        assert!(err.contains("USAGE_MAX_IDENTITIES"), "{err}");
+    }
+}
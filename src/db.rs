@@ -1,13 +1,86 @@
-use sqlx::{PgPool, postgres::PgPoolOptions};
+use once_cell::sync::OnceCell;
+use sqlx::{
+    PgPool, Postgres, QueryBuilder,
+    postgres::PgPoolOptions,
+    types::chrono::{DateTime, Utc},
+};
+use sha2::{Digest, Sha256};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::Duration;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tracing::warn;
 use crate::error::Result;
 
+/// IP 绑定审计事件（只保存 UUID 的哈希）
+#[derive(Debug, Clone)]
+pub struct AuthEvent {
+    pub uuid_hash: String,
+    pub bound_ip: String,
+    pub attempted_ip: String,
+    pub event_type: &'static str,
+}
+
+impl AuthEvent {
+    pub fn new(uuid: &str, bound_ip: &str, attempted_ip: &str, event_type: &'static str) -> Self {
+        Self {
+            uuid_hash: hex::encode(Sha256::digest(uuid.as_bytes())),
+            bound_ip: bound_ip.to_string(),
+            attempted_ip: attempted_ip.to_string(),
+            event_type,
+        }
+    }
+}
+
+/// 单次请求的访问日志（只保存调用方标识的哈希）
+#[derive(Debug, Clone)]
+pub struct RequestLogEntry {
+    pub identity_hash: String,
+    pub provider: &'static str,
+    pub chain: String,
+    pub method: &'static str,
+    pub status: String,
+    pub duration_ms: i64,
+    pub bytes: i64,
+    // 请求完成时间（而非批量写库时间）
+    pub created_at: DateTime<Utc>,
+}
+
+impl RequestLogEntry {
+    pub fn new(
+        identity: &str,
+        provider: &'static str,
+        chain: String,
+        method: &'static str,
+        status: String,
+        duration: Duration,
+        bytes: usize,
+    ) -> Self {
+        Self {
+            identity_hash: hex::encode(Sha256::digest(identity.as_bytes())),
+            provider,
+            chain,
+            method,
+            status,
+            duration_ms: duration.as_millis() as i64,
+            bytes: bytes as i64,
+            created_at: Utc::now(),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct PostgresDb {
     pub db_url: String,
     pub pool: PgPool,
 }
 
+// 统一的连接池参数
+fn pool_options() -> PgPoolOptions {
+    PgPoolOptions::new()
+        .max_connections(5)
+        .acquire_timeout(Duration::from_secs(3))
+}
+
 impl PostgresDb {
     pub fn new(db_url: String) -> Self {
         // 如果数据库URL为空，则使用默认值或跳过初始化
@@ -19,9 +92,7 @@ impl PostgresDb {
                 .connect_lazy("postgresql://placeholder@localhost/placeholder")
                 .expect("Failed to create placeholder pool")
         } else {
-            PgPoolOptions::new()
-                .max_connections(5)
-                .acquire_timeout(Duration::from_secs(3))
+            pool_options()
                 .connect_lazy(&db_url)
                 .expect("Failed to create pool")
         };
@@ -32,25 +103,154 @@ impl PostgresDb {
         }
     }
 
-    pub async fn update_db_url(&mut self, new_url: String) -> Result<()> {
-        let new_pool = PgPoolOptions::new()
-            .max_connections(5)
-            .connect(&new_url)
-            .await?;
-
-        // 测试写入
-        sqlx::query("CREATE TEMPORARY TABLE IF NOT EXISTS health_check (id SERIAL PRIMARY KEY)")
-            .execute(&new_pool)
-            .await?;
-        sqlx::query("INSERT INTO health_check DEFAULT VALUES")
-            .execute(&new_pool)
-            .await?;
-        sqlx::query("DROP TABLE health_check")
-            .execute(&new_pool)
-            .await?;
-
-        self.db_url = new_url;
-        self.pool = new_pool;
+    /// 就绪检查：在超时时间内执行 SELECT 1
+    pub async fn ping(&self, timeout: Duration) -> Result<()> {
+        tokio::time::timeout(timeout, sqlx::query("SELECT 1").execute(&self.pool))
+            .await
+            .map_err(|_| crate::error::AppError::Custom("database ping timed out".into()))??;
+        Ok(())
+    }
+
+    /// 执行 migrations/ 下的数据库迁移（幂等）
+    pub async fn run_migrations(&self) -> Result<()> {
+        sqlx::migrate!("./migrations").run(&self.pool).await?;
+        Ok(())
+    }
+
+    pub async fn insert_auth_event(&self, event: &AuthEvent) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO auth_events (uuid_hash, bound_ip, attempted_ip, event_type) VALUES ($1, $2, $3, $4)",
+        )
+        .bind(&event.uuid_hash)
+        .bind(&event.bound_ip)
+        .bind(&event.attempted_ip)
+        .bind(event.event_type)
+        .execute(&self.pool)
+        .await?;
         Ok(())
     }
-}
\ No newline at end of file
+
+    /// 读取某调用方当月已持久化的请求数，无记录时为 0
+    pub async fn load_monthly_usage(&self, identity_hash: &str, month: &str) -> Result<u64> {
+        let count: Option<i64> = sqlx::query_scalar(
+            "SELECT request_count FROM usage_monthly WHERE identity_hash = $1 AND month = $2",
+        )
+        .bind(identity_hash)
+        .bind(month)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(count.unwrap_or(0).max(0) as u64)
+    }
+
+    /// 累加月度请求数（按 (identity_hash, month) upsert）
+    pub async fn add_monthly_usage(&self, identity_hash: &str, month: &str, delta: u64) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO usage_monthly (identity_hash, month, request_count) VALUES ($1, $2, $3) \
+             ON CONFLICT (identity_hash, month) DO UPDATE \
+             SET request_count = usage_monthly.request_count + EXCLUDED.request_count, updated_at = now()",
+        )
+        .bind(identity_hash)
+        .bind(month)
+        .bind(delta as i64)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// 批量写入访问日志（单条多值 INSERT）
+    pub async fn insert_request_logs(&self, entries: &[RequestLogEntry]) -> Result<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+        let mut builder: QueryBuilder<Postgres> = QueryBuilder::new(
+            "INSERT INTO request_log (identity_hash, provider, chain, method, status, duration_ms, bytes, created_at) ",
+        );
+        builder.push_values(entries, |mut row, e| {
+            row.push_bind(&e.identity_hash)
+                .push_bind(e.provider)
+                .push_bind(&e.chain)
+                .push_bind(e.method)
+                .push_bind(&e.status)
+                .push_bind(e.duration_ms)
+                .push_bind(e.bytes)
+                .push_bind(e.created_at);
+        });
+        builder.build().execute(&self.pool).await?;
+        Ok(())
+    }
+}
+
+/// 启动审计事件写入任务，返回有界通道的发送端
+/// 写入失败只记录日志，不影响拦截器热路径
+pub fn spawn_auth_event_writer(db: PostgresDb, capacity: usize) -> mpsc::Sender<AuthEvent> {
+    let (tx, mut rx) = mpsc::channel::<AuthEvent>(capacity);
+    tokio::spawn(async move {
+        while let Some(event) = rx.recv().await {
+            if let Err(e) = db.insert_auth_event(&event).await {
+                warn!(error = %e, event_type = event.event_type, "failed to persist auth event");
+            }
+        }
+    });
+    tx
+}
+
+// 单批最多写入的访问日志条数，攒满后立即刷新，不等定时器
+const REQUEST_LOG_BATCH_SIZE: usize = 500;
+
+static REQUEST_LOG: OnceCell<mpsc::Sender<RequestLogEntry>> = OnceCell::new();
+
+// 因队列已满被丢弃的访问日志数，由 runtime_metrics_task 采样
+static REQUEST_LOG_DROPPED: AtomicU64 = AtomicU64::new(0);
+
+pub fn init_request_log(tx: mpsc::Sender<RequestLogEntry>) {
+    let _ = REQUEST_LOG.set(tx);
+}
+
+pub fn request_log_dropped() -> u64 {
+    REQUEST_LOG_DROPPED.load(Ordering::Relaxed)
+}
+
+/// 非阻塞投递访问日志；未启用数据库时忽略，队列已满时丢弃并计数
+pub fn record_request(entry: RequestLogEntry) {
+    if let Some(tx) = REQUEST_LOG.get()
+        && let Err(TrySendError::Full(_)) = tx.try_send(entry)
+    {
+        REQUEST_LOG_DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// 启动访问日志写入任务：按批量大小或 flush_interval 定期批量写库，返回有界通道的发送端
+pub fn spawn_request_log_writer(
+    db: PostgresDb,
+    capacity: usize,
+    flush_interval: Duration,
+) -> mpsc::Sender<RequestLogEntry> {
+    let (tx, mut rx) = mpsc::channel::<RequestLogEntry>(capacity);
+    tokio::spawn(async move {
+        let mut buffer = Vec::with_capacity(REQUEST_LOG_BATCH_SIZE);
+        let mut ticker = tokio::time::interval(flush_interval);
+        loop {
+            let closed = tokio::select! {
+                entry = rx.recv() => match entry {
+                    Some(entry) => {
+                        buffer.push(entry);
+                        if buffer.len() < REQUEST_LOG_BATCH_SIZE {
+                            continue;
+                        }
+                        false
+                    }
+                    None => true,
+                },
+                _ = ticker.tick() => false,
+            };
+            if let Err(e) = db.insert_request_logs(&buffer).await {
+                warn!(error = %e, count = buffer.len(), "failed to persist request logs");
+            }
+            buffer.clear();
+            if closed {
+                break;
+            }
+        }
+    });
+    tx
+}
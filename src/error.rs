@@ -26,6 +26,10 @@ pub enum AppError {
     #[error("Database error: {0}")]
     Database(#[from] sqlx::Error),
 
+    /// Database migration error
+    #[error("Migration error: {0}")]
+    Migrate(#[from] sqlx::migrate::MigrateError),
+
     /// Parse integer error
     #[error("Parse integer error: {0}")]
     ParseInt(#[from] std::num::ParseIntError),
@@ -42,10 +46,39 @@ pub enum AppError {
     #[error("TLS error: {0}")]
     Tls(#[from] rustls::Error),
 
+    /// Prometheus metrics error
+    #[error("Metrics error: {0}")]
+    Metrics(#[from] prometheus::Error),
+
+    /// Upstream returned an error HTTP status (401 / 403 / 429 / 5xx)
+    #[error("Upstream returned HTTP {0}")]
+    UpstreamStatus(u16),
+
     /// Custom error with message
     #[error("Application error: {0}")]
     Custom(String),
 }
 
+/// Boundary for code that returns `anyhow::Error`: converts into `Custom`,
+/// keeping the full context chain (`{:#}` formatting) in the message
+impl From<anyhow::Error> for AppError {
+    fn from(e: anyhow::Error) -> Self {
+        AppError::Custom(format!("{:#}", e))
+    }
+}
+
 // Type alias for convenience
-pub type Result<T> = std::result::Result<T, AppError>;
\ No newline at end of file
+pub type Result<T> = std::result::Result<T, AppError>;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn anyhow_conversion_keeps_context_chain() {
+        let error = anyhow::anyhow!("inner").context("outer");
+        match AppError::from(error) {
+            AppError::Custom(message) => assert_eq!(message, "outer: inner"),
+            other => panic!("unexpected variant: {other:?}"),
+        }
+    }
+}
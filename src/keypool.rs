@@ -0,0 +1,158 @@
+// src/keypool.rs
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// 上游 API key 池：轮询分配，返回 429 或 401/403 的 key 在冷却期内被跳过
+/// 同时持有上游基础地址，key 追加在路径末尾组成请求地址
+#[derive(Debug)]
+pub struct ApiKeyPool {
+    base_url: String,
+    keys: Vec<String>,
+    next: AtomicUsize,
+    // 每个 key 的冷却截止时间（相对 started 的毫秒数），0 表示未冷却
+    cooldown_until: Vec<AtomicU64>,
+    cooldown: Duration,
+    started: Instant,
+    // 上游以 401/403 拒绝 key 的累计次数，由 runtime_metrics_task 采样
+    auth_failures: AtomicU64,
+}
+
+impl ApiKeyPool {
+    pub fn new(base_url: String, keys: Vec<String>, cooldown: Duration) -> Self {
+        let cooldown_until = keys.iter().map(|_| AtomicU64::new(0)).collect();
+        Self {
+            base_url,
+            keys,
+            next: AtomicUsize::new(0),
+            cooldown_until,
+            cooldown,
+            started: Instant::now(),
+            auth_failures: AtomicU64::new(0),
+        }
+    }
+
+    fn now_millis(&self) -> u64 {
+        self.started.elapsed().as_millis() as u64
+    }
+
+    pub fn has_keys(&self) -> bool {
+        !self.keys.is_empty()
+    }
+
+    /// 带 key 的上游请求地址
+    pub fn endpoint(&self, key: &str) -> String {
+        format!("{}{}", self.base_url, key)
+    }
+
+    pub fn key_count(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn auth_failures(&self) -> u64 {
+        self.auth_failures.load(Ordering::Relaxed)
+    }
+
+    /// 轮询选择下一个不在冷却中的 key；全部冷却时仍按轮询返回，未配置 key 时返回空串
+    pub fn next_key(&self) -> &str {
+        if self.keys.is_empty() {
+            return "";
+        }
+        let now = self.now_millis();
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        for offset in 0..self.keys.len() {
+            let idx = (start + offset) % self.keys.len();
+            if self.cooldown_until[idx].load(Ordering::Acquire) <= now {
+                return &self.keys[idx];
+            }
+        }
+        &self.keys[start % self.keys.len()]
+    }
+
+    /// 标记 key 被上游限流，冷却期内不再优先分配
+    pub fn mark_rate_limited(&self, key: &str) {
+        if let Some(idx) = self.cool_down(key) {
+            warn!(
+                key_index = idx,
+                cooldown_secs = self.cooldown.as_secs(),
+                "upstream rate-limited API key, cooling down"
+            );
+        }
+    }
+
+    /// 标记 key 被上游拒绝（401/403，多为过期或已轮换），同样进入冷却
+    pub fn mark_auth_failed(&self, key: &str) {
+        self.auth_failures.fetch_add(1, Ordering::Relaxed);
+        if let Some(idx) = self.cool_down(key) {
+            warn!(
+                key_index = idx,
+                cooldown_secs = self.cooldown.as_secs(),
+                "upstream rejected API key, cooling down"
+            );
+        }
+    }
+
+    fn cool_down(&self, key: &str) -> Option<usize> {
+        let idx = self.keys.iter().position(|k| k == key)?;
+        let until = self.now_millis() + self.cooldown.as_millis() as u64;
+        self.cooldown_until[idx].store(until, Ordering::Release);
+        Some(idx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool(cooldown: Duration) -> ApiKeyPool {
+        let keys = vec!["key-a".into(), "key-b".into(), "key-c".into()];
+        ApiKeyPool::new("https://rpc.ankr.com/multichain/".into(), keys, cooldown)
+    }
+
+    fn take(pool: &ApiKeyPool, n: usize) -> Vec<String> {
+        (0..n).map(|_| pool.next_key().to_string()).collect()
+    }
+
+    #[test]
+    fn next_key_cycles_through_all_keys() {
+        let pool = pool(Duration::from_secs(60));
+        assert_eq!(take(&pool, 6), ["key-a", "key-b", "key-c", "key-a", "key-b", "key-c"]);
+        assert_eq!(pool.endpoint("key-b"), "https://rpc.ankr.com/multichain/key-b");
+    }
+
+    #[test]
+    fn rate_limited_key_is_skipped_until_cooldown_elapses() {
+        let pool = pool(Duration::from_millis(50));
+        pool.mark_rate_limited("key-b");
+        assert!(!take(&pool, 6).contains(&"key-b".to_string()));
+
+        std::thread::sleep(Duration::from_millis(80));
+        assert!(take(&pool, 3).contains(&"key-b".to_string()));
+    }
+
+    #[test]
+    fn auth_failure_cools_down_the_key_and_is_counted() {
+        let pool = pool(Duration::from_secs(60));
+        pool.mark_auth_failed("key-a");
+        pool.mark_auth_failed("unknown-key");
+        assert_eq!(pool.auth_failures(), 2);
+        assert!(!take(&pool, 6).contains(&"key-a".to_string()));
+    }
+
+    #[test]
+    fn all_keys_cooling_down_still_returns_a_key() {
+        let pool = pool(Duration::from_secs(60));
+        for key in ["key-a", "key-b", "key-c"] {
+            pool.mark_rate_limited(key);
+        }
+        // 全部冷却时仍按轮询分配，而不是返回空 key
+        assert_eq!(take(&pool, 3), ["key-a", "key-b", "key-c"]);
+    }
+
+    #[test]
+    fn empty_pool_returns_an_empty_key() {
+        let pool = ApiKeyPool::new(String::new(), Vec::new(), Duration::from_secs(60));
+        assert!(!pool.has_keys());
+        assert_eq!(pool.next_key(), "");
+    }
+}
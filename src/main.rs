@@ -1,112 +1,356 @@
 // src/main.rs
 use crate::{
-    client::GLOBAL_STATE,
-    error::Result,
-    pb::ankr::ankr_indexer_server::AnkrIndexerServer,
-    rules::RateLimitInterceptor,
+    ankr::init_upstream_limits,
+    blocklist::{IpBlocklist, init_ip_blocklist, ip_blocklist_reload_task},
+    client::{GLOBAL_STATE, init_auth_events, init_connection_config},
+    config::Config,
+    db::{init_request_log, spawn_auth_event_writer, spawn_request_log_writer},
+    error::{AppError, Result},
+    metrics::runtime_metrics_task,
+    pb::{FILE_DESCRIPTOR_SET, ankr::ankr_indexer_server::AnkrIndexerServer},
+    routes::build_router,
+    rules::{RULE_REGISTRY, RateLimitInterceptor, init_rate_rules},
+    shutdown::{drain_watchdog, graceful_shutdown, shutdown_signal},
     state::{AppState, IndexService},
-    utils::load_rustls_config,
+    telemetry::init_tracing,
+    usage::{UsageTracker, init_usage_tracker, usage_flush_task},
+    utils::{init_secrets, load_rustls_config},
+};
+use axum::{Extension, Router, extract::ConnectInfo};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo, TokioTimer},
+    server::conn::auto,
+    service::TowerToHyperService,
 };
-use hyper::{Body, Request, Response, service::service_fn};
 use rustls::ServerConfig;
-use std::{convert::Infallible, net::SocketAddr, sync::Arc};
-use tokio::net::TcpListener;
-use tokio::time::{Duration, interval};
+use std::{
+    net::SocketAddr,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Semaphore;
+use tokio::time::{Duration, interval, timeout};
 use tokio_rustls::TlsAcceptor;
-use tonic::transport::{Identity, Server, ServerTlsConfig};
-use tonic_async_interceptor::AsyncInterceptedService; // Added for async interceptor support
+use tonic::transport::{Certificate, Identity, Server, ServerTlsConfig};
+use tonic_async_interceptor::AsyncInterceptedService;
+use tonic_health::server::HealthReporter;
+use tracing::{debug, info, warn};
 
 mod ankr;
+mod blocklist;
 mod client;
+mod config;
 mod db;
 mod error;
+mod keypool;
+mod metrics;
 mod pb;
+mod routes;
 mod rules;
+mod shutdown;
 mod state;
+mod telemetry;
+#[cfg(test)]
+mod test_support;
+mod usage;
 mod utils;
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    tracing_subscriber::fmt::init();
-
-    // 1. 证书读取
-    let cert_pem = tokio::fs::read("./cert.pem").await?;
-    let key_pem = tokio::fs::read("./key.pem").await?;
+    // 1. 加载配置（含 .env），再初始化日志与可选的 OTLP 导出
+    let config = Config::from_env()?;
+    let tracer_provider = init_tracing(&config.otlp_endpoint)?;
+    let mut secrets = config.ankr_api_keys.clone();
+    secrets.push(config.database_url.clone());
+    secrets.push(config.admin_token.clone());
+    secrets.push(config.metrics_token.clone());
+    secrets.push(config.metrics_basic_auth.clone());
+    init_secrets(secrets);
+    let cert_pem = tokio::fs::read(&config.tls_cert_path).await?;
+    let key_pem = tokio::fs::read(&config.tls_key_path).await?;
     rustls::crypto::ring::default_provider()
         .install_default()
         .ok();
 
-    // 2. 准备服务实例
-    let state = Arc::new(AppState::new());
+    if config.ankr_api_keys.is_empty() {
+        warn!(
+            "ANKR_API_KEY / ANKR_API_KEYS not set: indexer requests will fail with FAILED_PRECONDITION \
+             until a key is configured"
+        );
+    }
+    if !config.metrics_allow_unauthenticated
+        && config.metrics_token.is_empty()
+        && config.metrics_basic_auth.is_empty()
+        && config.admin_token.is_empty()
+    {
+        warn!(
+            "no METRICS_TOKEN, METRICS_BASIC_AUTH or ADMIN_TOKEN set: /metrics and /admin/rules will answer 401 \
+             (set METRICS_ALLOW_UNAUTHENTICATED=true to expose them without credentials)"
+        );
+    }
+
+    // 2. 连接生命周期配置（需在 GLOBAL_STATE 首次使用前设置）
+    let heartbeat_interval = config.connection.heartbeat_interval;
+    init_rate_rules(config.rate_rules.clone());
+    init_connection_config(config.connection.clone());
+    for (service, policy) in &config.connection.ip_policies {
+        RULE_REGISTRY.set_ip_policy(service, *policy);
+        info!(%service, %policy, "IP binding policy overridden");
+    }
+    init_upstream_limits(&config.http_client);
+
+    // 可选的客户端 IP 黑名单：启动时加载失败直接退出，之后定期检查文件变化
+    let ip_blocklist = if config.ip_blocklist_path.is_empty() {
+        None
+    } else {
+        let blocklist = Arc::new(IpBlocklist::load(&config.ip_blocklist_path).await?);
+        init_ip_blocklist(blocklist.clone());
+        Some(blocklist)
+    };
 
-    // 业务服务：挂载鉴权拦截器 (check JWT)
+    // 准备服务实例
+    let state = Arc::new(AppState::new(&config)?);
+
+    // 数据库迁移 + 审计事件 / 访问日志异步写入 Postgres（仅在配置了数据库时启用）
+    if !state.db.db_url.is_empty() {
+        if let Err(e) = state.db.run_migrations().await {
+            warn!(error = %e, "database migrations failed");
+        }
+        init_auth_events(spawn_auth_event_writer(state.db.clone(), config.auth_event_queue_size));
+        init_request_log(spawn_request_log_writer(
+            state.db.clone(),
+            config.request_log_queue_size,
+            config.request_log_flush_interval,
+        ));
+    }
+
+    // 月度用量统计：配置了数据库时定期写库，否则只在内存中计数
+    let db = (!state.db.db_url.is_empty()).then(|| state.db.clone());
+    let usage_tracker = Arc::new(UsageTracker::new(
+        config.monthly_request_quota,
+        config.usage_max_identities,
+        db,
+    ));
+    init_usage_tracker(usage_tracker.clone());
+
+    // 业务服务：挂载拦截器（UUID / mTLS 身份、IP 绑定、限流与月度配额）
     let indexer = IndexService {
         state: state.clone(),
     };
 
     let rate_limit = RateLimitInterceptor { rule_name: "ankr" };
 
-    // Changed to use AsyncInterceptedService
     let ankr_svc = AsyncInterceptedService::new(AnkrIndexerServer::new(indexer), rate_limit);
     
     // 4. 构建 gRPC 路由层
-    let grpc_addr = "0.0.0.0:50051".parse()?;
+    let grpc_addr = config.grpc_addr;
     let grpc_identity = Identity::from_pem(&cert_pem, &key_pem);
+    let mut grpc_tls = ServerTlsConfig::new().identity(grpc_identity);
+
+    // 可选 mTLS：要求客户端出示由指定 CA 签发的证书
+    if !config.mtls_ca_path.is_empty() {
+        let ca_pem = tokio::fs::read(&config.mtls_ca_path).await?;
+        grpc_tls = grpc_tls
+            .client_ca_root(Certificate::from_pem(ca_pem))
+            .client_auth_optional(config.mtls_optional);
+    }
+
+    // gRPC reflection（可选），便于 grpcurl 等工具发现服务
+    let reflection_svc = if config.grpc_reflection {
+        let svc = tonic_reflection::server::Builder::configure()
+            .register_encoded_file_descriptor_set(FILE_DESCRIPTOR_SET)
+            .build_v1()
+            .map_err(|e| AppError::Custom(format!("failed to build reflection service: {}", e)))?;
+        Some(svc)
+    } else {
+        None
+    };
+
+    // grpc.health.v1：启动完成后标记 SERVING，关闭时切换为 NOT_SERVING
+    let (health_reporter, health_svc) = tonic_health::server::health_reporter();
+    health_reporter.set_serving::<AnkrIndexerServer<IndexService>>().await;
+
+    // Ctrl+C / SIGTERM：先报告 NOT_SERVING，等待 SHUTDOWN_GRACE_SECS 后再停止接收请求
+    let shutting_down = Arc::new(AtomicBool::new(false));
+    let draining = Arc::new(AtomicBool::new(false));
+    let shutdown = graceful_shutdown::<AnkrIndexerServer<IndexService>>(
+        shutdown_signal(),
+        health_reporter.clone(),
+        shutting_down.clone(),
+        draining.clone(),
+        config.shutdown_grace_period,
+    );
+
+    // 统计进行中的请求，关闭时等待其完成（最长 SHUTDOWN_DRAIN_TIMEOUT_SECS）
+    let in_flight = state.in_flight.clone();
+    let drain = drain_watchdog(in_flight.counter(), draining, config.shutdown_drain_timeout);
 
     let grpc_server = Server::builder()
-        .tls_config(ServerTlsConfig::new().identity(grpc_identity))?
+        .tls_config(grpc_tls)?
+        .max_concurrent_streams(config.http2_max_concurrent_streams)
+        .layer(in_flight)
+        .add_service(health_svc)
         .add_service(ankr_svc) // 注册业务服务 (Protected)
-        .serve(grpc_addr);
+        .add_optional_service(reflection_svc)
+        .serve_with_shutdown(grpc_addr, shutdown);
 
-    // 5. Health Server (不做变动)
-    let http_addr = "0.0.0.0:8443".parse()?;
-    let http_tls_config = Arc::new(load_rustls_config(&cert_pem, &key_pem)?);
-    let http_server = run_health_server(http_addr, http_tls_config);
+    // 5. HTTPS 端口：axum 路由（健康检查、/metrics、管理端点）+ 指标中间件，TLS 握手后交给 hyper 处理
+    let http_addr = config.http_addr;
+    let http_tls_config = Arc::new(load_rustls_config(
+        &cert_pem,
+        &key_pem,
+        &[b"h2", b"http/1.1"],
+    )?);
+    let http_server = run_health_server(http_addr, http_tls_config, state.clone());
 
     // 6. 启动心跳检测任务
-    let heartbeat_server = heartbeat_task();
+    let heartbeat_server = heartbeat_task(heartbeat_interval);
+    let runtime_metrics = runtime_metrics_task(state.clone(), heartbeat_interval);
+    let usage_flush = usage_flush_task(usage_tracker.clone(), config.usage_flush_interval);
+    let blocklist_reload = ip_blocklist_reload_task(ip_blocklist, config.ip_blocklist_reload_interval);
+    let grpc_health = grpc_health_task(
+        health_reporter,
+        state.clone(),
+        heartbeat_interval,
+        shutting_down,
+    );
+
+    info!(%grpc_addr, %http_addr, "gRPC and HTTPS servers listening");
 
-    println!("gRPC Server listening on {}", grpc_addr);
+    // gRPC 优雅关闭后直接退出；其余任务出错同样终止进程
+    tokio::select! {
+        res = grpc_server => res?,
+        res = http_server => res?,
+        res = heartbeat_server => res?,
+        res = grpc_health => res?,
+        res = runtime_metrics => res?,
+        res = drain => res?,
+        res = usage_flush => res?,
+        res = blocklist_reload => res?,
+    }
+
+    // 写入尚未持久化的用量
+    usage_tracker.flush().await;
 
-    tokio::try_join!(
-        async { grpc_server.await.map_err(error::AppError::from) },
-        async { http_server.await.map_err(error::AppError::from) },
-        async { heartbeat_server.await.map_err(error::AppError::from) }
-    )?;
+    // 刷新尚未导出的 span
+    if let Some(provider) = tracer_provider
+        && let Err(e) = provider.shutdown()
+    {
+        warn!(error = %e, "failed to flush OTLP spans");
+    }
 
     Ok(())
 }
 
-// --- 极简 Health Check (保留给 Cloudflare) ---
-async fn health_handler(_: Request<Body>) -> std::result::Result<Response<Body>, Infallible> {
-    Ok(Response::new(Body::from("OK")))
+// 按数据库可用性更新 gRPC 健康状态（未配置数据库时保持 SERVING）
+// 进入关闭流程后不再改写状态，保持 NOT_SERVING
+async fn grpc_health_task(
+    reporter: HealthReporter,
+    state: Arc<AppState>,
+    period: Duration,
+    shutting_down: Arc<AtomicBool>,
+) -> Result<()> {
+    if state.db.db_url.is_empty() {
+        return std::future::pending().await;
+    }
+    let mut interval = interval(period);
+    loop {
+        interval.tick().await;
+        if shutting_down.load(Ordering::Acquire) {
+            return std::future::pending().await;
+        }
+        match state.db.ping(Duration::from_secs(2)).await {
+            Ok(()) => reporter.set_serving::<AnkrIndexerServer<IndexService>>().await,
+            Err(e) => {
+                warn!(error = %e, "database unavailable, reporting NOT_SERVING");
+                reporter.set_not_serving::<AnkrIndexerServer<IndexService>>().await;
+            }
+        }
+    }
 }
 
-async fn run_health_server(addr: SocketAddr, tls_config: Arc<ServerConfig>) -> Result<()> {
+// HTTPS 端口：TLS 握手后交给 axum 路由处理（健康检查与指标）
+// 并发连接数受 HTTP_MAX_CONNECTIONS 限制，超出时直接关闭新连接
+async fn run_health_server(
+    addr: SocketAddr,
+    tls_config: Arc<ServerConfig>,
+    state: Arc<AppState>,
+) -> Result<()> {
     let acceptor = TlsAcceptor::from(tls_config);
     let listener = TcpListener::bind(addr).await?;
+    let config = state.config.clone();
+    let connections = Arc::new(Semaphore::new(state.config.http_max_connections));
+    let metrics = state.metrics.clone();
+    let app = build_router(state);
     loop {
-        let (stream, _) = listener.accept().await?;
+        let (stream, peer) = listener.accept().await?;
+        let Ok(permit) = connections.clone().try_acquire_owned() else {
+            metrics.https_connections_rejected_total.inc();
+            debug!(%peer, "HTTPS connection limit reached, refusing connection");
+            continue;
+        };
         let acceptor = acceptor.clone();
+        let app = app.clone();
+        let config = config.clone();
+        let metrics = metrics.clone();
+        metrics.https_connections.inc();
         tokio::spawn(async move {
-            if let Ok(tls_stream) = acceptor.accept(stream).await {
-                let _ = hyper::server::conn::Http::new()
-                    .serve_connection(tls_stream, service_fn(health_handler))
-                    .await;
-            }
+            serve_tls_connection(stream, peer, acceptor, app, &config).await;
+            metrics.https_connections.dec();
+            drop(permit);
         });
     }
 }
 
+async fn serve_tls_connection(
+    stream: TcpStream,
+    peer: SocketAddr,
+    acceptor: TlsAcceptor,
+    app: Router,
+    config: &Config,
+) {
+    // 握手超时直接断开，避免慢速连接无限期占用任务
+    let tls_stream = match timeout(config.tls_handshake_timeout, acceptor.accept(stream)).await {
+        Ok(Ok(tls_stream)) => tls_stream,
+        Ok(Err(e)) => {
+            debug!(%peer, error = %e, "TLS handshake failed");
+            return;
+        }
+        Err(_) => {
+            warn!(%peer, "TLS handshake timed out, dropping connection");
+            return;
+        }
+    };
+    // HTTP/1.1 限制请求头读取时间；HTTP/2 定期 ping，回收无响应的空闲连接
+    let mut builder = auto::Builder::new(TokioExecutor::new());
+    builder
+        .http1()
+        .timer(TokioTimer::new())
+        .header_read_timeout(config.http_header_read_timeout);
+    builder
+        .http2()
+        .timer(TokioTimer::new())
+        .max_concurrent_streams(config.http2_max_concurrent_streams)
+        .keep_alive_interval(config.http_keepalive_interval);
+    // 对端地址供 ConnectInfo 提取（/ratelimit/status 按客户端 IP 校验身份）
+    let app = app.layer(Extension(ConnectInfo(peer)));
+    let _ = builder
+        .serve_connection(TokioIo::new(tls_stream), TowerToHyperService::new(app))
+        .await;
+}
+
 // 心跳检测任务，定期清理过期连接
-async fn heartbeat_task() -> Result<()> {
-    let mut interval = interval(Duration::from_secs(30)); // 每30秒检查一次
+async fn heartbeat_task(period: Duration) -> Result<()> {
+    let mut interval = interval(period);
     loop {
         interval.tick().await;
 
         // 清理过期连接
         GLOBAL_STATE.cleanup_expired_connections().await;
 
-        println!("Heartbeat check completed");
+        debug!("heartbeat check completed");
     }
 }
\ No newline at end of file
@@ -0,0 +1,235 @@
+// src/metrics.rs
+use crate::{
+    ankr::upstream_in_flight,
+    client::{GLOBAL_STATE, capacity_evictions},
+    blocklist::blocked_requests,
+    db::request_log_dropped,
+    error::Result,
+    state::AppState,
+};
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::{StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+/// Prometheus 指标集合，随 AppState 共享
+#[derive(Clone, Debug)]
+pub struct PrometheusMetrics {
+    pub registry: Registry,
+    // HTTP 请求计数 (method, path, status)
+    pub http_requests_total: IntCounterVec,
+    // HTTP 请求耗时 (method, path)
+    pub http_request_duration_seconds: HistogramVec,
+    // GLOBAL_STATE 中的客户端状态条目数
+    pub client_states: IntGauge,
+    // 处于连接状态的客户端数
+    pub active_connections: IntGauge,
+    // 正在进行中的上游请求数
+    pub upstream_in_flight: IntGauge,
+    // 因容量上限被淘汰的客户端状态数
+    pub client_capacity_evictions_total: IntCounter,
+    // 因队列已满被丢弃的访问日志数
+    pub request_log_dropped_total: IntCounter,
+    // HTTPS 端口当前连接数与因超出上限被拒绝的连接数
+    pub https_connections: IntGauge,
+    pub https_connections_rejected_total: IntCounter,
+    // 上游以 401/403 拒绝 API key 的次数
+    pub upstream_key_auth_failures_total: IntCounter,
+    // 因客户端 IP 命中黑名单被拒绝的请求数
+    pub ip_blocklist_rejections_total: IntCounter,
+}
+
+impl PrometheusMetrics {
+    pub fn new() -> Result<Self> {
+        let registry = Registry::new_custom(Some("zeno_gateway".into()), None)?;
+
+        let http_requests_total = IntCounterVec::new(
+            Opts::new("http_requests_total", "Total HTTP requests"),
+            &["method", "path", "status"],
+        )?;
+        let http_request_duration_seconds = HistogramVec::new(
+            HistogramOpts::new("http_request_duration_seconds", "HTTP request latency"),
+            &["method", "path"],
+        )?;
+
+        let client_states = IntGauge::new("client_states", "Client states held in memory")?;
+        let active_connections =
+            IntGauge::new("active_connections", "Connections tracked by the heartbeat task")?;
+        let upstream_in_flight =
+            IntGauge::new("upstream_in_flight", "Upstream requests currently in flight")?;
+
+        let client_capacity_evictions_total = IntCounter::new(
+            "client_capacity_evictions_total",
+            "Client states evicted because the store reached CLIENT_MAX_STATES",
+        )?;
+
+        let request_log_dropped_total = IntCounter::new(
+            "request_log_dropped_total",
+            "Access log entries dropped because the write queue was full",
+        )?;
+
+        let https_connections =
+            IntGauge::new("https_connections", "Open connections on the HTTPS port")?;
+        let https_connections_rejected_total = IntCounter::new(
+            "https_connections_rejected_total",
+            "HTTPS connections refused because HTTP_MAX_CONNECTIONS was reached",
+        )?;
+
+        let upstream_key_auth_failures_total = IntCounter::new(
+            "upstream_key_auth_failures_total",
+            "Upstream requests rejected with 401/403 because of the API key",
+        )?;
+
+        let ip_blocklist_rejections_total = IntCounter::new(
+            "ip_blocklist_rejections_total",
+            "Requests rejected because the client IP matched IP_BLOCKLIST_PATH",
+        )?;
+
+        registry.register(Box::new(http_requests_total.clone()))?;
+        registry.register(Box::new(http_request_duration_seconds.clone()))?;
+        registry.register(Box::new(client_states.clone()))?;
+        registry.register(Box::new(active_connections.clone()))?;
+        registry.register(Box::new(upstream_in_flight.clone()))?;
+        registry.register(Box::new(client_capacity_evictions_total.clone()))?;
+        registry.register(Box::new(request_log_dropped_total.clone()))?;
+        registry.register(Box::new(https_connections.clone()))?;
+        registry.register(Box::new(https_connections_rejected_total.clone()))?;
+        registry.register(Box::new(upstream_key_auth_failures_total.clone()))?;
+        registry.register(Box::new(ip_blocklist_rejections_total.clone()))?;
+
+        Ok(Self {
+            registry,
+            http_requests_total,
+            http_request_duration_seconds,
+            client_states,
+            active_connections,
+            upstream_in_flight,
+            client_capacity_evictions_total,
+            request_log_dropped_total,
+            https_connections,
+            https_connections_rejected_total,
+            upstream_key_auth_failures_total,
+            ip_blocklist_rejections_total,
+        })
+    }
+
+    /// 以 Prometheus 文本格式导出全部指标
+    pub fn render(&self) -> Result<String> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8_lossy(&buffer).into_owned())
+    }
+}
+
+// 定期采样运行时状态到 gauge，用于排查连接泄漏
+pub async fn runtime_metrics_task(state: Arc<AppState>, period: Duration) -> Result<()> {
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+        state.metrics.client_states.set(GLOBAL_STATE.get_store().entry_count() as i64);
+        state.metrics.active_connections.set(GLOBAL_STATE.active_connections() as i64);
+        state.metrics.upstream_in_flight.set(upstream_in_flight() as i64);
+        // 计数器只能递增：补齐与全局计数的差值
+        let evictions = &state.metrics.client_capacity_evictions_total;
+        evictions.inc_by(capacity_evictions().saturating_sub(evictions.get()));
+        let dropped = &state.metrics.request_log_dropped_total;
+        dropped.inc_by(request_log_dropped().saturating_sub(dropped.get()));
+        let auth_failures = &state.metrics.upstream_key_auth_failures_total;
+        auth_failures.inc_by(state.ankr_keys.auth_failures().saturating_sub(auth_failures.get()));
+        let blocked = &state.metrics.ip_blocklist_rejections_total;
+        blocked.inc_by(blocked_requests().saturating_sub(blocked.get()));
+    }
+}
+
+pub async fn metrics_handler(State(state): State<Arc<AppState>>) -> Response {
+    match state.metrics.render() {
+        Ok(body) => ([(header::CONTENT_TYPE, TextEncoder::new().format_type().to_string())], body)
+            .into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+// 记录每个请求的计数与耗时；path 使用路由模板，避免高基数标签
+pub async fn metrics_middleware(
+    State(state): State<Arc<AppState>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let method = req.method().to_string();
+    let path = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|p| p.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let start = Instant::now();
+
+    let response = next.run(req).await;
+    let elapsed = start.elapsed();
+    let status = response.status();
+
+    state
+        .metrics
+        .http_request_duration_seconds
+        .with_label_values(&[&method, &path])
+        .observe(elapsed.as_secs_f64());
+    state
+        .metrics
+        .http_requests_total
+        .with_label_values(&[&method, &path, status.as_str()])
+        .inc();
+
+    let is_error = status.is_client_error() || status.is_server_error();
+    if state.log_sampler.should_log(&path, is_error) {
+        info!(
+            %method,
+            %path,
+            status = status.as_u16(),
+            elapsed_ms = elapsed.as_millis() as u64,
+            "http request"
+        );
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn gauge_value(body: &str, name: &str) -> i64 {
+        body.lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+            .unwrap_or_else(|| panic!("{name} missing from:\n{body}"))
+            .parse()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn runtime_metrics_task_samples_client_states() {
+        let state = Arc::new(AppState::new(&Config::default()).unwrap());
+        GLOBAL_STATE.admit_request("metrics-sampled-uuid", "192.0.2.1", "ankr").await.unwrap();
+        GLOBAL_STATE.get_store().run_pending_tasks().await;
+
+        // interval 的第一次 tick 立即触发：超时前恰好完成一次采样
+        let sampled = tokio::time::timeout(
+            Duration::from_millis(50),
+            runtime_metrics_task(state.clone(), Duration::from_secs(60)),
+        )
+        .await;
+        assert!(sampled.is_err());
+
+        let body = state.metrics.render().unwrap();
+        assert!(gauge_value(&body, "zeno_gateway_client_states") >= 1, "{body}");
+        assert!(gauge_value(&body, "zeno_gateway_active_connections") >= 1, "{body}");
+    }
+}
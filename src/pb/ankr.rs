@@ -35,8 +35,22 @@ pub struct AnkrTxHisRequest {
     pub address: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
     #[prost(string, tag = "6")]
     pub page_token: ::prost::alloc::string::String,
+    /// 与 from/to_timestamp 互斥
+    #[prost(message, optional, tag = "7")]
+    pub from_block: ::core::option::Option<BlockReference>,
+    #[prost(message, optional, tag = "8")]
+    pub to_block: ::core::option::Option<BlockReference>,
+    /// 未设置时默认 true（倒序）
+    #[prost(bool, optional, tag = "9")]
+    pub desc_order: ::core::option::Option<bool>,
+    /// 未设置时默认 false
+    #[prost(bool, optional, tag = "10")]
+    pub include_logs: ::core::option::Option<bool>,
+    /// true 时跳过缓存，强制回源
+    #[prost(bool, tag = "11")]
+    pub no_cache: bool,
 }
-#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+#[derive(Clone, PartialEq, ::prost::Message)]
 pub struct TransactionHistoryEntry {
     #[prost(string, tag = "1")]
     pub tx_hash: ::prost::alloc::string::String,
@@ -56,6 +70,20 @@ pub struct TransactionHistoryEntry {
     pub gas_price: ::prost::alloc::string::String,
     #[prost(string, tag = "9")]
     pub gas_used: ::prost::alloc::string::String,
+    /// 仅在 include_logs = true 时填充
+    #[prost(message, repeated, tag = "10")]
+    pub logs: ::prost::alloc::vec::Vec<TxLog>,
+}
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct TxLog {
+    #[prost(string, tag = "1")]
+    pub address: ::prost::alloc::string::String,
+    #[prost(string, repeated, tag = "2")]
+    pub topics: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    #[prost(string, tag = "3")]
+    pub data: ::prost::alloc::string::String,
+    #[prost(string, tag = "4")]
+    pub log_index: ::prost::alloc::string::String,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct TxHistoryList {
@@ -63,6 +91,9 @@ pub struct TxHistoryList {
     pub txs: ::prost::alloc::vec::Vec<TransactionHistoryEntry>,
     #[prost(string, tag = "2")]
     pub next_page_token: ::prost::alloc::string::String,
+    /// 多链请求中上游失败的链（其余链正常返回）
+    #[prost(string, repeated, tag = "3")]
+    pub failed_chains: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
 }
 #[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
 pub struct HotAsset {
@@ -95,6 +126,13 @@ pub struct HotAsset {
 pub struct HotAssetList {
     #[prost(message, repeated, tag = "1")]
     pub assets: ::prost::alloc::vec::Vec<HotAsset>,
+    /// 多链请求中上游失败的链（其余链正常返回）
+    #[prost(string, repeated, tag = "2")]
+    pub failed_chains: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    /// 余额或 NFT 达到条目上限时的续页 token，原样填入 AnkrAssetRequest.page_token 继续拉取；
+    /// 流式响应中每条消息的 token 只续拉该消息所属的一路（余额或 NFT）
+    #[prost(string, tag = "3")]
+    pub next_page_token: ::prost::alloc::string::String,
 }
 #[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
 pub struct AnkrAssetRequest {
@@ -109,6 +147,9 @@ pub struct AnkrAssetRequest {
     pub only_whitelisted: bool,
     #[prost(string, tag = "6")]
     pub page_token: ::prost::alloc::string::String,
+    /// true 时跳过缓存，强制回源
+    #[prost(bool, tag = "7")]
+    pub no_cache: bool,
 }
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
 #[repr(i32)]
@@ -120,6 +161,8 @@ pub enum Blockchain {
     Linea = 4,
     Optimism = 5,
     EthSepolia = 6,
+    Polygon = 7,
+    Bsc = 8,
 }
 impl Blockchain {
     /// String value of the enum field names used in the ProtoBuf definition.
@@ -135,6 +178,8 @@ impl Blockchain {
             Self::Linea => "LINEA",
             Self::Optimism => "OPTIMISM",
             Self::EthSepolia => "ETH_SEPOLIA",
+            Self::Polygon => "POLYGON",
+            Self::Bsc => "BSC",
         }
     }
     /// Creates an enum from field names used in the ProtoBuf definition.
@@ -147,6 +192,8 @@ impl Blockchain {
             "LINEA" => Some(Self::Linea),
             "OPTIMISM" => Some(Self::Optimism),
             "ETH_SEPOLIA" => Some(Self::EthSepolia),
+            "POLYGON" => Some(Self::Polygon),
+            "BSC" => Some(Self::Bsc),
             _ => None,
         }
     }
@@ -284,6 +331,56 @@ pub mod ankr_indexer_client {
                 .insert(GrpcMethod::new("ankr.AnkrIndexer", "GetAssetBalance"));
             self.inner.unary(req, path, codec).await
         }
+        /// 逐页推送交易历史：每条消息对应上游一页，next_page_token 可用于断点续拉
+        pub async fn stream_transaction_history(
+            &mut self,
+            request: impl tonic::IntoRequest<super::AnkrTxHisRequest>,
+        ) -> std::result::Result<
+            tonic::Response<tonic::codec::Streaming<super::TxHistoryList>>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic_prost::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/ankr.AnkrIndexer/StreamTransactionHistory",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("ankr.AnkrIndexer", "StreamTransactionHistory"));
+            self.inner.server_streaming(req, path, codec).await
+        }
+        /// 余额与 NFT 两路并发翻页，每拉到一页即推送一条 HotAssetList
+        pub async fn stream_asset_balance(
+            &mut self,
+            request: impl tonic::IntoRequest<super::AnkrAssetRequest>,
+        ) -> std::result::Result<
+            tonic::Response<tonic::codec::Streaming<super::HotAssetList>>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic_prost::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/ankr.AnkrIndexer/StreamAssetBalance",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("ankr.AnkrIndexer", "StreamAssetBalance"));
+            self.inner.server_streaming(req, path, codec).await
+        }
     }
 }
 /// Generated server implementations.
@@ -307,6 +404,34 @@ pub mod ankr_indexer_server {
             &self,
             request: tonic::Request<super::AnkrAssetRequest>,
         ) -> std::result::Result<tonic::Response<super::HotAssetList>, tonic::Status>;
+        /// Server streaming response type for the StreamTransactionHistory method.
+        type StreamTransactionHistoryStream: tonic::codegen::tokio_stream::Stream<
+                Item = std::result::Result<super::TxHistoryList, tonic::Status>,
+            >
+            + std::marker::Send
+            + 'static;
+        /// 逐页推送交易历史：每条消息对应上游一页，next_page_token 可用于断点续拉
+        async fn stream_transaction_history(
+            &self,
+            request: tonic::Request<super::AnkrTxHisRequest>,
+        ) -> std::result::Result<
+            tonic::Response<Self::StreamTransactionHistoryStream>,
+            tonic::Status,
+        >;
+        /// Server streaming response type for the StreamAssetBalance method.
+        type StreamAssetBalanceStream: tonic::codegen::tokio_stream::Stream<
+                Item = std::result::Result<super::HotAssetList, tonic::Status>,
+            >
+            + std::marker::Send
+            + 'static;
+        /// 余额与 NFT 两路并发翻页，每拉到一页即推送一条 HotAssetList
+        async fn stream_asset_balance(
+            &self,
+            request: tonic::Request<super::AnkrAssetRequest>,
+        ) -> std::result::Result<
+            tonic::Response<Self::StreamAssetBalanceStream>,
+            tonic::Status,
+        >;
     }
     #[derive(Debug)]
     pub struct AnkrIndexerServer<T> {
@@ -475,6 +600,103 @@ pub mod ankr_indexer_server {
                     };
                     Box::pin(fut)
                 }
+                "/ankr.AnkrIndexer/StreamTransactionHistory" => {
+                    #[allow(non_camel_case_types)]
+                    struct StreamTransactionHistorySvc<T: AnkrIndexer>(pub Arc<T>);
+                    impl<
+                        T: AnkrIndexer,
+                    > tonic::server::ServerStreamingService<super::AnkrTxHisRequest>
+                    for StreamTransactionHistorySvc<T> {
+                        type Response = super::TxHistoryList;
+                        type ResponseStream = T::StreamTransactionHistoryStream;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::ResponseStream>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::AnkrTxHisRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as AnkrIndexer>::stream_transaction_history(
+                                        &inner,
+                                        request,
+                                    )
+                                    .await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = StreamTransactionHistorySvc(inner);
+                        let codec = tonic_prost::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.server_streaming(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/ankr.AnkrIndexer/StreamAssetBalance" => {
+                    #[allow(non_camel_case_types)]
+                    struct StreamAssetBalanceSvc<T: AnkrIndexer>(pub Arc<T>);
+                    impl<
+                        T: AnkrIndexer,
+                    > tonic::server::ServerStreamingService<super::AnkrAssetRequest>
+                    for StreamAssetBalanceSvc<T> {
+                        type Response = super::HotAssetList;
+                        type ResponseStream = T::StreamAssetBalanceStream;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::ResponseStream>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::AnkrAssetRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as AnkrIndexer>::stream_asset_balance(&inner, request)
+                                    .await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = StreamAssetBalanceSvc(inner);
+                        let codec = tonic_prost::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.server_streaming(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
                 _ => {
                     Box::pin(async move {
                         let mut response = http::Response::new(
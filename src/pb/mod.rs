@@ -1 +1,4 @@
-pub mod ankr;
\ No newline at end of file
+pub mod ankr;
+
+// build.rs 生成的描述符集，供 gRPC reflection 服务使用
+pub const FILE_DESCRIPTOR_SET: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/ankr_descriptor.bin"));
\ No newline at end of file
@@ -0,0 +1,451 @@
+// src/routes.rs
+use crate::{
+    ankr::{INDEXER_METHODS, probe_upstream, supported_chains},
+    client::GLOBAL_STATE,
+    config::Config,
+    pb::ankr::ankr_indexer_server::SERVICE_NAME,
+    metrics::{metrics_handler, metrics_middleware},
+    rules::RULE_REGISTRY,
+    state::{AppState, Maintenance},
+    utils::{extract_http_client_ip, redact, validate_device_id},
+};
+use axum::{
+    Json, Router,
+    extract::{ConnectInfo, Query, Request, State},
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use serde::Deserialize;
+use serde_json::Value;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tower_http::compression::CompressionLayer;
+use tracing::warn;
+
+/// 构建 HTTPS 端口上的路由：健康检查与指标
+pub fn build_router(state: Arc<AppState>) -> Router {
+    // 指标与限流规则需要鉴权，见 require_metrics_auth
+    let protected = Router::new()
+        .route("/metrics", get(metrics_handler))
+        .route("/admin/rules", get(rules_handler))
+        .route_layer(middleware::from_fn_with_state(state.clone(), require_metrics_auth));
+
+    Router::new()
+        .route("/health", get(health_handler))
+        .route("/health/ready", get(ready_handler))
+        .route("/version", get(version_handler))
+        .route("/chains", get(chains_handler))
+        .route("/ratelimit/status", get(rate_limit_status_handler))
+        .merge(protected)
+        .route("/debug/config", get(debug_config_handler))
+        .route(
+            "/admin/maintenance",
+            get(maintenance_status_handler).post(set_maintenance_handler),
+        )
+        .layer(middleware::from_fn_with_state(state.clone(), metrics_middleware))
+        // 按 Accept-Encoding 压缩 (gzip / br)；已带 Content-Encoding 的响应不会重复压缩
+        .layer(CompressionLayer::new())
+        .with_state(state)
+}
+
+// --- 极简 Health Check (保留给 Cloudflare) ---
+// /health 为廉价的存活探针
+async fn health_handler() -> &'static str {
+    "OK"
+}
+
+// /version 返回构建信息，便于排障时确认运行中的版本（build.rs 注入）
+async fn version_handler() -> Json<Value> {
+    Json(serde_json::json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "git_sha": env!("GIT_SHA"),
+        "build_timestamp": env!("BUILD_TIMESTAMP"),
+        "rustc_version": env!("RUSTC_VERSION"),
+    }))
+}
+
+// /chains 列出索引服务支持的链（枚举名与上游链名）及可用方法
+async fn chains_handler(State(state): State<Arc<AppState>>) -> Json<Value> {
+    let chains: Vec<Value> = supported_chains()
+        .into_iter()
+        .map(|(name, upstream)| serde_json::json!({ "name": name, "upstream_name": upstream }))
+        .collect();
+    Json(serde_json::json!({
+        "providers": [{
+            "name": "ankr",
+            "configured": state.ankr_keys.has_keys(),
+            "service": SERVICE_NAME,
+            "methods": INDEXER_METHODS,
+            "chains": chains,
+        }],
+    }))
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ReadyQuery {
+    #[serde(default)]
+    deep: bool,
+}
+
+// /health/ready 检查数据库，?deep=true 时额外检查上游 RPC
+// 深度检查会向上游发起请求，与 /metrics 使用相同的鉴权，避免匿名请求借此放大上游流量
+async fn ready_handler(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<ReadyQuery>,
+    headers: HeaderMap,
+) -> (StatusCode, Json<Value>) {
+    if query.deep
+        && !state.config.metrics_allow_unauthenticated
+        && !metrics_credentials_valid(&state.config, &headers)
+    {
+        return admin_error(StatusCode::UNAUTHORIZED);
+    }
+    let mut checks = serde_json::Map::new();
+    let mut ready = true;
+
+    // 未配置 DATABASE_URL 时跳过数据库检查
+    let db_check = if state.db.db_url.is_empty() {
+        serde_json::json!({ "status": "skipped" })
+    } else {
+        match state.db.ping(Duration::from_secs(2)).await {
+            Ok(()) => serde_json::json!({ "status": "ok" }),
+            Err(e) => {
+                ready = false;
+                serde_json::json!({ "status": "down", "error": redact(&e.to_string()) })
+            }
+        }
+    };
+    checks.insert("database".into(), db_check);
+
+    // 维护模式下存活探针保持正常，但就绪探针失败，让负载均衡摘除流量
+    if state.maintenance.any_enabled() {
+        ready = false;
+        checks.insert("maintenance".into(), serde_json::json!({ "status": "enabled" }));
+    }
+
+    if query.deep {
+        let probe = probe_upstream(&state.client, &state.config.ankr_multichain_url, Duration::from_secs(3));
+        let upstream_check = match probe.await {
+            Ok(()) => serde_json::json!({ "status": "ok" }),
+            Err(e) => {
+                warn!(error = %redact(&e.to_string()), "readiness upstream probe failed");
+                ready = false;
+                serde_json::json!({ "status": "down", "error": "upstream RPC unreachable" })
+            }
+        };
+        checks.insert("upstream_rpc".into(), upstream_check);
+    }
+
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    let body = serde_json::json!({
+        "status": if ready { "ready" } else { "unavailable" },
+        "checks": checks,
+    });
+
+    (status, Json(body))
+}
+
+fn default_service() -> String {
+    "ankr".to_string()
+}
+
+#[derive(Debug, Deserialize)]
+struct RateLimitQuery {
+    #[serde(default = "default_service")]
+    service: String,
+}
+
+// /ratelimit/status 查询调用方在某服务上的剩余配额，不消耗令牌、不创建状态
+// 身份与 gRPC 相同：uuid 请求头 + 客户端 IP 须符合该 UUID 当前的 IP 绑定
+async fn rate_limit_status_handler(
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Query(query): Query<RateLimitQuery>,
+) -> (StatusCode, Json<Value>) {
+    let Some(uuid) = headers.get("uuid").and_then(|v| v.to_str().ok()) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({ "error": "missing uuid header" })),
+        );
+    };
+    if let Err(status) = validate_device_id(uuid) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({ "error": status.message() })),
+        );
+    }
+
+    let ip = extract_http_client_ip(&headers, peer);
+    match GLOBAL_STATE.rate_limit_status(uuid, &ip, &query.service).await {
+        Ok(status) => (
+            StatusCode::OK,
+            Json(serde_json::json!({
+                "service": query.service,
+                "burst": status.burst,
+                "replenish_interval_secs": status.replenish_interval.as_secs_f64(),
+                "available": status.available,
+                "full_in_secs": status.full_in.as_secs_f64(),
+            })),
+        ),
+        Err(status) => {
+            let code = match status.code() {
+                tonic::Code::PermissionDenied => StatusCode::FORBIDDEN,
+                _ => StatusCode::NOT_FOUND,
+            };
+            (code, Json(serde_json::json!({ "error": status.message() })))
+        }
+    }
+}
+
+// /admin/rules 列出所有限流规则及其配额
+async fn rules_handler() -> Json<Value> {
+    let rules: Vec<Value> = RULE_REGISTRY
+        .list()
+        .into_iter()
+        .map(|(name, rule)| {
+            serde_json::json!({
+                "service": name,
+                "quota": rule.to_string(),
+                "count": rule.count,
+                "window": rule.window.as_str(),
+                "burst": rule.burst(),
+                "stream_limit": rule.stream_limit,
+                "ip_policy": rule.ip_policy.to_string(),
+            })
+        })
+        .collect();
+    Json(serde_json::json!({ "rules": rules }))
+}
+
+// 校验 Authorization: Bearer <ADMIN_TOKEN>；未配置 token 时管理端点视为不存在
+fn check_admin_token(state: &AppState, headers: &HeaderMap) -> std::result::Result<(), StatusCode> {
+    let expected = state.config.admin_token.as_bytes();
+    if expected.is_empty() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let provided = authorization(headers)
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .unwrap_or_default()
+        .as_bytes();
+    if constant_time_eq(provided, expected) { Ok(()) } else { Err(StatusCode::UNAUTHORIZED) }
+}
+
+fn authorization(headers: &HeaderMap) -> Option<&str> {
+    headers.get(header::AUTHORIZATION).and_then(|v| v.to_str().ok())
+}
+
+// 常量时间比较，避免通过响应耗时猜测凭据
+fn constant_time_eq(provided: &[u8], expected: &[u8]) -> bool {
+    provided.len() == expected.len()
+        && provided.iter().zip(expected).fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+// Bearer 接受 METRICS_TOKEN 或 ADMIN_TOKEN；Basic 与 METRICS_BASIC_AUTH 比较
+fn metrics_credentials_valid(config: &Config, headers: &HeaderMap) -> bool {
+    let Some(auth) = authorization(headers) else {
+        return false;
+    };
+    if let Some(token) = auth.strip_prefix("Bearer ") {
+        return [&config.metrics_token, &config.admin_token]
+            .iter()
+            .any(|expected| !expected.is_empty() && constant_time_eq(token.as_bytes(), expected.as_bytes()));
+    }
+    if let Some(encoded) = auth.strip_prefix("Basic ")
+        && !config.metrics_basic_auth.is_empty()
+    {
+        return BASE64
+            .decode(encoded.trim())
+            .is_ok_and(|decoded| constant_time_eq(&decoded, config.metrics_basic_auth.as_bytes()));
+    }
+    false
+}
+
+// /metrics 与 /admin/rules 的鉴权；仅在 METRICS_ALLOW_UNAUTHENTICATED=true 时允许匿名访问
+async fn require_metrics_auth(
+    State(state): State<Arc<AppState>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let config = &state.config;
+    if config.metrics_allow_unauthenticated || metrics_credentials_valid(config, req.headers()) {
+        return next.run(req).await;
+    }
+    let challenge = if config.metrics_basic_auth.is_empty() {
+        "Bearer"
+    } else {
+        "Basic realm=\"zeno-gateway\""
+    };
+    let mut response = admin_error(StatusCode::UNAUTHORIZED).into_response();
+    response
+        .headers_mut()
+        .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static(challenge));
+    response
+}
+
+fn admin_error(status: StatusCode) -> (StatusCode, Json<Value>) {
+    let error = status.canonical_reason().unwrap_or("error").to_lowercase();
+    (status, Json(serde_json::json!({ "error": error })))
+}
+
+// /debug/config 返回生效配置（密钥已遮蔽），以及规则名、链数量等派生信息
+async fn debug_config_handler(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> (StatusCode, Json<Value>) {
+    if let Err(status) = check_admin_token(&state, &headers) {
+        return admin_error(status);
+    }
+    let rules: Vec<String> = RULE_REGISTRY.list().into_iter().map(|(name, _)| name).collect();
+    (
+        StatusCode::OK,
+        Json(serde_json::json!({
+            "config": state.config.redacted_json(),
+            "rules": rules,
+            "ankr_key_count": state.config.ankr_api_keys.len(),
+            "supported_chain_count": supported_chains().len(),
+        })),
+    )
+}
+
+fn maintenance_json(state: &AppState) -> Value {
+    let subsystems: serde_json::Map<String, Value> = Maintenance::SUBSYSTEMS
+        .iter()
+        .map(|s| (s.to_string(), Value::Bool(state.maintenance.is_enabled(s))))
+        .collect();
+    serde_json::json!({
+        "subsystems": subsystems,
+        "retry_after_secs": state.maintenance.retry_after.as_secs(),
+    })
+}
+
+// GET /admin/maintenance 查看各子系统的维护开关
+async fn maintenance_status_handler(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> (StatusCode, Json<Value>) {
+    if let Err(status) = check_admin_token(&state, &headers) {
+        return admin_error(status);
+    }
+    (StatusCode::OK, Json(maintenance_json(&state)))
+}
+
+#[derive(Debug, Deserialize)]
+struct MaintenanceRequest {
+    subsystem: String,
+    enabled: bool,
+}
+
+// POST /admin/maintenance {"subsystem": "indexer", "enabled": true} 开关维护模式
+async fn set_maintenance_handler(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(req): Json<MaintenanceRequest>,
+) -> (StatusCode, Json<Value>) {
+    if let Err(status) = check_admin_token(&state, &headers) {
+        return admin_error(status);
+    }
+    if !state.maintenance.set(&req.subsystem, req.enabled) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "error": format!("unknown subsystem {:?}", req.subsystem),
+                "supported": Maintenance::SUBSYSTEMS,
+            })),
+        );
+    }
+    warn!(subsystem = %req.subsystem, enabled = req.enabled, "maintenance mode changed");
+    (StatusCode::OK, Json(maintenance_json(&state)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::MockUpstream;
+    use axum::body::{Body, to_bytes};
+    use tower::ServiceExt;
+
+    const METRICS_TOKEN: &str = "metrics-token";
+
+    fn router() -> Router {
+        let config = Config {
+            metrics_token: METRICS_TOKEN.into(),
+            ..Config::default()
+        };
+        build_router(Arc::new(AppState::new(&config).unwrap()))
+    }
+
+    fn get(uri: &str) -> axum::http::request::Builder {
+        Request::builder().uri(uri)
+    }
+
+    #[tokio::test]
+    async fn metrics_returns_prometheus_text_format() {
+        let app = router();
+        let response = app.clone().oneshot(get("/health").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = app
+            .oneshot(
+                get("/metrics")
+                    .header(header::AUTHORIZATION, format!("Bearer {METRICS_TOKEN}"))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let content_type = response.headers()[header::CONTENT_TYPE].to_str().unwrap();
+        assert!(content_type.starts_with("text/plain; version=0.0.4"), "{content_type}");
+
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("# HELP zeno_gateway_http_requests_total Total HTTP requests"), "{body}");
+        assert!(body.contains("# TYPE zeno_gateway_http_requests_total counter"), "{body}");
+        assert!(
+            body.contains(r#"zeno_gateway_http_requests_total{method="GET",path="/health",status="200"} 1"#),
+            "{body}"
+        );
+        assert!(body.contains("# TYPE zeno_gateway_http_request_duration_seconds histogram"), "{body}");
+    }
+
+    #[tokio::test]
+    async fn metrics_requires_credentials() {
+        let response = router().oneshot(get("/metrics").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.headers()[header::WWW_AUTHENTICATE], "Bearer");
+    }
+
+    #[tokio::test]
+    async fn deep_readiness_requires_credentials_and_probes_upstream() {
+        let upstream = MockUpstream::start().await;
+        let config = Config {
+            metrics_token: METRICS_TOKEN.into(),
+            ..upstream.config()
+        };
+        let app = build_router(Arc::new(AppState::new(&config).unwrap()));
+
+        let response = app.clone().oneshot(get("/health/ready?deep=true").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = app
+            .oneshot(
+                get("/health/ready?deep=true")
+                    .header(header::AUTHORIZATION, format!("Bearer {METRICS_TOKEN}"))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await.unwrap()).unwrap();
+        assert_eq!(body["checks"]["upstream_rpc"]["status"], "ok");
+    }
+}
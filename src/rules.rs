@@ -1,73 +1,221 @@
 // rules.rs
 use crate::{
-    utils::extract_client_ip,
-    client::GLOBAL_STATE};  
+    blocklist::check_ip_blocklist,
+    utils::{extract_client_cert_identity, extract_client_ip, validate_device_id},
+    client::GLOBAL_STATE,
+    usage::check_monthly_quota};  
 use governor::{Quota};  
 use std::collections::HashMap;  
+use std::fmt;
 use std::num::NonZeroU32;  
 use std::sync::RwLock;  
-use once_cell::sync::Lazy;  
+use once_cell::sync::{Lazy, OnceCell};  
 use tonic::{Request, Status};
 use std::pin::Pin;
 use std::future::Future;
+use std::str::FromStr;
+use std::time::Duration;
 
 
 
 
+// 配额时间窗口
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QuotaWindow {
+    Minute,
+    Hour,
+}
+
+impl QuotaWindow {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QuotaWindow::Minute => "minute",
+            QuotaWindow::Hour => "hour",
+        }
+    }
+}
+
+impl FromStr for QuotaWindow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "minute" => Ok(QuotaWindow::Minute),
+            "hour" => Ok(QuotaWindow::Hour),
+            other => Err(format!("expected minute or hour, got {other:?}")),
+        }
+    }
+}
+
+// 同一 UUID 从新 IP 发起请求时的处理策略
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IpPolicy {
+    // 首次绑定后拒绝其他 IP（默认）
+    Strict,
+    // 允许切换网络：改绑到新 IP 并记录审计事件，适合移动端
+    AllowRoaming,
+    // window 内最多允许 max_ips 个不同 IP，超过 window 未出现的 IP 不再计数
+    AllowN { max_ips: usize, window: Duration },
+}
+
+// 文本格式与 IP_POLICIES 一致："strict" | "allow_roaming" | "allow_n:<max_ips>:<window_secs>"
+impl fmt::Display for IpPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IpPolicy::Strict => write!(f, "strict"),
+            IpPolicy::AllowRoaming => write!(f, "allow_roaming"),
+            IpPolicy::AllowN { max_ips, window } => {
+                write!(f, "allow_n:{}:{}", max_ips, window.as_secs())
+            }
+        }
+    }
+}
+
+impl FromStr for IpPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "strict" => Ok(IpPolicy::Strict),
+            "allow_roaming" => Ok(IpPolicy::AllowRoaming),
+            other => {
+                let parse = |v: Option<&str>| v.and_then(|v| v.parse::<u64>().ok()).filter(|&v| v > 0);
+                let mut parts = other.strip_prefix("allow_n:").unwrap_or_default().split(':');
+                match (parse(parts.next()), parse(parts.next()), parts.next()) {
+                    (Some(max_ips), Some(window_secs), None) => Ok(IpPolicy::AllowN {
+                        max_ips: max_ips as usize,
+                        window: Duration::from_secs(window_secs),
+                    }),
+                    _ => Err(format!(
+                        "expected strict, allow_roaming or allow_n:<max_ips>:<window_secs>, got {other:?}"
+                    )),
+                }
+            }
+        }
+    }
+}
+
 // 定义一个服务的限流规则  
 #[derive(Clone, Debug)]  
 pub struct ServiceRule {  
     // 令牌桶配额 (例如: 100 req / 10 min)  
     pub quota: Quota,  
+    // 每个窗口的请求数与窗口长度（Quota 不暴露这些参数，单独保存用于展示）
+    pub count: u32,
+    pub window: QuotaWindow,
     // 该服务允许的最大并发连接数 (例如: 严格服务要求用户总连接数 <= 2)  
     pub stream_limit: u64,
+    // UUID 与 IP 的绑定策略
+    pub ip_policy: IpPolicy,
 }  
+
+impl ServiceRule {
+    pub fn new(count: NonZeroU32, window: QuotaWindow, burst: NonZeroU32, stream_limit: u64) -> Self {
+        let quota = match window {
+            QuotaWindow::Minute => Quota::per_minute(count),
+            QuotaWindow::Hour => Quota::per_hour(count),
+        };
+        Self {
+            quota: quota.allow_burst(burst),
+            count: count.get(),
+            window,
+            stream_limit,
+            ip_policy: IpPolicy::Strict,
+        }
+    }
+
+    pub fn burst(&self) -> u32 {
+        self.quota.burst_size().get()
+    }
+}
+
+// 人类可读的配额描述，例如 "10/hour, burst 3"
+impl fmt::Display for ServiceRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}, burst {}", self.count, self.window.as_str(), self.burst())
+    }
+}
   
-// 全局规则注册表  
-pub static RULE_REGISTRY: Lazy<RuleRegistry> = Lazy::new(|| {  
-    let mut r = RuleRegistry::new();  
-      
+/// 内置的限流规则，未配置 RATE_RULES_PATH 时使用
+pub fn default_rate_rules() -> HashMap<String, ServiceRule> {
+    let mut rules = HashMap::new();
+
     // === 配置规则 1: Metadata Service (普通高频服务) ===  
     // 1分钟 10 次，突发 5 次，允许用户最多开 3 个连接  
-    r.register("metadata", ServiceRule {  
-        quota: Quota::per_minute(NonZeroU32::new(20).unwrap())  
-            .allow_burst(NonZeroU32::new(5).unwrap()),  
-        stream_limit: 100,
-    });  
+    rules.insert("metadata".to_string(), ServiceRule::new(
+        NonZeroU32::new(20).unwrap(),
+        QuotaWindow::Minute,
+        NonZeroU32::new(5).unwrap(),
+        100,
+    ));  
   
     // === 配置规则 2: Ankr Service (中等频率服务) ===  
     // 1小时 10 次，突发 3 次，允许用户最多开 1 个连接  
-    r.register("ankr", ServiceRule {  
-        quota: Quota::per_hour(NonZeroU32::new(10).unwrap())  
-            .allow_burst(NonZeroU32::new(3).unwrap()),  
-        stream_limit: 50,
-    });
+    rules.insert("ankr".to_string(), ServiceRule::new(
+        NonZeroU32::new(10).unwrap(),
+        QuotaWindow::Hour,
+        NonZeroU32::new(3).unwrap(),
+        50,
+    ));
 
     // === 配置规则 4: Price Feed (价格信息服务) ===  
     // 1分钟 10 次，突发 5 次，允许用户最多开 2 个连接  
-    r.register("standard", ServiceRule {  
-        quota: Quota::per_minute(NonZeroU32::new(10).unwrap())  
-            .allow_burst(NonZeroU32::new(5).unwrap()),  
-        stream_limit: 200,
-    });  
+    rules.insert("standard".to_string(), ServiceRule::new(
+        NonZeroU32::new(10).unwrap(),
+        QuotaWindow::Minute,
+        NonZeroU32::new(5).unwrap(),
+        200,
+    ));  
   
-    r  
-});  
+    rules
+}
+
+// 启动时由配置设置的限流规则，需在首次访问 RULE_REGISTRY 之前通过 init_rate_rules 设置
+static RATE_RULES: OnceCell<HashMap<String, ServiceRule>> = OnceCell::new();
+
+pub fn init_rate_rules(rules: HashMap<String, ServiceRule>) {
+    let _ = RATE_RULES.set(rules);
+}
+
+// 全局规则注册表  
+pub static RULE_REGISTRY: Lazy<RuleRegistry> = Lazy::new(|| {
+    RuleRegistry::new(RATE_RULES.get().cloned().unwrap_or_else(default_rate_rules))
+});
   
 pub struct RuleRegistry {  
     rules: RwLock<HashMap<String, ServiceRule>>,  
 }  
   
 impl RuleRegistry {  
-    fn new() -> Self { Self { rules: RwLock::new(HashMap::new()) } }  
-      
-    fn register(&mut self, name: &str, rule: ServiceRule) {  
-        self.rules.write().unwrap().insert(name.to_string(), rule);  
-    }  
+    fn new(rules: HashMap<String, ServiceRule>) -> Self { Self { rules: RwLock::new(rules) } }  
   
     pub fn get(&self, name: &str) -> Option<ServiceRule> {  
         self.rules.read().unwrap().get(name).cloned()  
     }  
+
+    // 启动时按配置 (IP_POLICIES) 覆盖服务的 IP 绑定策略；服务不存在时返回 false
+    pub fn set_ip_policy(&self, name: &str, ip_policy: IpPolicy) -> bool {
+        match self.rules.write().unwrap().get_mut(name) {
+            Some(rule) => {
+                rule.ip_policy = ip_policy;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // 列出全部规则（按服务名排序），供管理端展示
+    pub fn list(&self) -> Vec<(String, ServiceRule)> {
+        let mut rules: Vec<_> = self
+            .rules
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(name, rule)| (name.clone(), rule.clone()))
+            .collect();
+        rules.sort_by(|a, b| a.0.cmp(&b.0));
+        rules
+    }
 }
 
 
@@ -81,36 +229,41 @@ impl tonic_async_interceptor::AsyncInterceptor for RateLimitInterceptor {
 
     fn call(&mut self, req: Request<()>) -> Self::Future {
         let rule_name = self.rule_name;
-        let uuid = match req.metadata()
-            .get("uuid")
-            .and_then(|v| v.to_str().ok())
-            .ok_or_else(|| Status::invalid_argument("Missing UUID metadata"))
-            .map(|s| s.to_string()) {
-                Ok(uuid) => uuid,
-                Err(status) => return Box::pin(async move { Err(status) }),
-            };
-
-        if uuid.len() != 128 { 
-            return Box::pin(async move { Err(Status::invalid_argument("Invalid UUID")) });
-        }
+
+        // mTLS 客户端证书身份优先，否则使用 UUID metadata
+        let uuid = if let Some(identity) = extract_client_cert_identity(&req) {
+            format!("cert:{}", identity)
+        } else {
+            let uuid = match req.metadata()
+                .get("uuid")
+                .and_then(|v| v.to_str().ok())
+                .ok_or_else(|| Status::invalid_argument("Missing UUID metadata"))
+                .map(|s| s.to_string()) {
+                    Ok(uuid) => uuid,
+                    Err(status) => return Box::pin(async move { Err(status) }),
+                };
+
+            if let Err(status) = validate_device_id(&uuid) {
+                return Box::pin(async move { Err(status) });
+            }
+            uuid
+        };
 
         let ip = extract_client_ip(&req);
         if ip.len() > 45 || ip.len() < 7 { 
             return Box::pin(async move { Err(Status::invalid_argument("Invalid IP format")) });
         }
+        // IP 黑名单在限流之前检查，被拒绝的请求不消耗令牌
+        if let Err(status) = check_ip_blocklist(&ip) {
+            return Box::pin(async move { Err(status) });
+        }
 
         Box::pin(async move {
-            // 使用异步方式获取客户端状态
-            let client_option = GLOBAL_STATE.get_store().get(&uuid).await;
-            if let Some(client) = client_option {
-                client.try_consume_token(rule_name)
-                    .map_err(|e| Status::resource_exhausted(format!("Rate limit exceeded: {}", e)))?;
-                GLOBAL_STATE.update_client_state(uuid, ip).await
-                    .map_err(|e| Status::internal(format!("Failed to update client state: {}", e)))?;
-            } else {
-                GLOBAL_STATE.init_client_state(&uuid, &ip, rule_name).await
-                    .map_err(|e| Status::internal(format!("Failed to initialize client state: {}", e)))?;
-            }
+            // IP 策略校验在扣除令牌之前；错误状态（permission_denied / resource_exhausted）原样返回
+            GLOBAL_STATE.admit_request(&uuid, &ip, rule_name).await?;
+
+            // 月度配额（按月计费），在短窗口限流之后检查
+            check_monthly_quota(&uuid).await?;
 
             Ok(req)
         })
@@ -120,5 +273,31 @@ impl tonic_async_interceptor::AsyncInterceptor for RateLimitInterceptor {
 
 //客户端示例
 // let mut req = tonic::Request::new(AnkrTxHisRequest::default());
-// req.metadata_mut().insert("uuid", "user-123".parse().unwrap());
-// client.get_tx_history(req).await?;
\ No newline at end of file
+// req.metadata_mut().insert("uuid", "550e8400-e29b-41d4-a716-446655440000".parse().unwrap());
+// client.get_tx_history(req).await?;
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ip_policy_round_trips_through_display_and_from_str() {
+        let policies = [
+            IpPolicy::Strict,
+            IpPolicy::AllowRoaming,
+            IpPolicy::AllowN { max_ips: 2, window: Duration::from_secs(3600) },
+        ];
+        for policy in policies {
+            assert_eq!(policy.to_string().parse::<IpPolicy>(), Ok(policy));
+        }
+        assert_eq!(" strict ".parse::<IpPolicy>(), Ok(IpPolicy::Strict));
+    }
+
+    #[test]
+    fn ip_policy_rejects_malformed_values() {
+        for bad in ["", "roaming", "allow_n", "allow_n:2", "allow_n:0:60", "allow_n:2:0", "allow_n:2:60:1", "allow_n:x:60"] {
+            assert!(bad.parse::<IpPolicy>().is_err(), "{bad:?} should not parse");
+        }
+    }
+}
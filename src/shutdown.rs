@@ -0,0 +1,266 @@
+// src/shutdown.rs
+use crate::error::Result;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::time::{Instant, interval};
+use tonic::server::NamedService;
+use tonic_health::server::HealthReporter;
+use tower::{Layer, Service};
+use tracing::{info, warn};
+
+/// 统计进行中的 gRPC 请求数，关闭时用于等待请求完成
+/// 流式响应在 handler 返回后仍由后台任务推送，任务需通过 guard() 自行计数
+#[derive(Clone, Debug, Default)]
+pub struct InFlightLayer {
+    count: Arc<AtomicUsize>,
+}
+
+impl InFlightLayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn counter(&self) -> Arc<AtomicUsize> {
+        self.count.clone()
+    }
+
+    /// 计入一个进行中的请求，guard 释放时结束
+    pub fn guard(&self) -> InFlightGuard {
+        InFlightGuard::new(self.count.clone())
+    }
+}
+
+impl<S> Layer<S> for InFlightLayer {
+    type Service = InFlightService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        InFlightService {
+            inner,
+            count: self.count.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct InFlightService<S> {
+    inner: S,
+    count: Arc<AtomicUsize>,
+}
+
+// 请求结束（包括被取消）时自动减计数
+pub struct InFlightGuard(Arc<AtomicUsize>);
+
+impl InFlightGuard {
+    fn new(count: Arc<AtomicUsize>) -> Self {
+        count.fetch_add(1, Ordering::AcqRel);
+        Self(count)
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+impl<S, Req> Service<Req> for InFlightService<S>
+where
+    S: Service<Req>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = std::result::Result<S::Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<std::result::Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        let guard = InFlightGuard::new(self.count.clone());
+        let fut = self.inner.call(req);
+        Box::pin(async move {
+            let res = fut.await;
+            drop(guard);
+            res
+        })
+    }
+}
+
+/// 等待 Ctrl+C 或 SIGTERM（容器编排停止实例时发送）
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c().await.ok();
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{SignalKind, signal};
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(e) => {
+                warn!(error = %e, "failed to install SIGTERM handler");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => info!("received Ctrl+C"),
+        _ = terminate => info!("received SIGTERM"),
+    }
+}
+
+/// 优雅关闭的前半段：收到 signal 后先把服务 S 报告为 NOT_SERVING（shutting_down 置位，
+/// 健康检查任务不再改写状态），等待 grace 让负载均衡摘除实例，期间照常处理请求；
+/// 之后 draining 置位并返回，调用方据此停止 gRPC server 并开始排空
+pub async fn graceful_shutdown<S: NamedService>(
+    signal: impl Future<Output = ()>,
+    reporter: HealthReporter,
+    shutting_down: Arc<AtomicBool>,
+    draining: Arc<AtomicBool>,
+    grace: Duration,
+) {
+    signal.await;
+    shutting_down.store(true, Ordering::Release);
+    reporter.set_not_serving::<S>().await;
+    info!(grace_secs = grace.as_secs(), "reported NOT_SERVING, waiting before draining");
+    tokio::time::sleep(grace).await;
+    info!("draining gRPC connections");
+    draining.store(true, Ordering::Release);
+}
+
+/// 关闭阶段的看门狗：开始排空（draining）后每秒检查剩余请求数，
+/// 全部完成或超过 drain_timeout 时返回，由调用方退出进程
+pub async fn drain_watchdog(
+    in_flight: Arc<AtomicUsize>,
+    draining: Arc<AtomicBool>,
+    drain_timeout: Duration,
+) -> Result<()> {
+    let mut ticker = interval(Duration::from_secs(1));
+    let mut deadline: Option<Instant> = None;
+    loop {
+        ticker.tick().await;
+        if !draining.load(Ordering::Acquire) {
+            continue;
+        }
+        let deadline = *deadline.get_or_insert_with(|| Instant::now() + drain_timeout);
+        let remaining = in_flight.load(Ordering::Acquire);
+        if remaining == 0 {
+            info!("all in-flight requests completed");
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            warn!(remaining, "drain timeout elapsed, exiting with requests in flight");
+            return Ok(());
+        }
+        info!(remaining, "draining in-flight requests");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn service_counts_request_until_response() {
+        let layer = InFlightLayer::new();
+        let counter = layer.counter();
+        let release = Arc::new(tokio::sync::Notify::new());
+        let wait = release.clone();
+        let service = layer.layer(tower::service_fn(move |_: ()| {
+            let wait = wait.clone();
+            async move {
+                wait.notified().await;
+                Ok::<_, std::convert::Infallible>(())
+            }
+        }));
+
+        let call = tokio::spawn(service.oneshot(()));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(counter.load(Ordering::Acquire), 1);
+
+        release.notify_one();
+        call.await.unwrap().unwrap();
+        assert_eq!(counter.load(Ordering::Acquire), 0);
+    }
+
+    #[tokio::test]
+    async fn drain_waits_for_in_flight_request() {
+        let layer = InFlightLayer::new();
+        let guard = layer.guard();
+        let shutting_down = Arc::new(AtomicBool::new(true));
+        let start = Instant::now();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(1500)).await;
+            drop(guard);
+        });
+
+        drain_watchdog(layer.counter(), shutting_down, Duration::from_secs(30))
+            .await
+            .unwrap();
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(1500), "returned before request finished: {elapsed:?}");
+        assert!(elapsed < Duration::from_secs(5), "did not return once drained: {elapsed:?}");
+    }
+
+    #[tokio::test]
+    async fn drain_gives_up_after_timeout() {
+        let layer = InFlightLayer::new();
+        let _stuck = layer.guard();
+        let shutting_down = Arc::new(AtomicBool::new(true));
+
+        let start = Instant::now();
+        drain_watchdog(layer.counter(), shutting_down, Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(layer.counter().load(Ordering::Acquire), 1);
+    }
+
+    #[tokio::test]
+    async fn health_reports_serving_then_not_serving_before_draining() {
+        use crate::pb::ankr::ankr_indexer_server::AnkrIndexerServer;
+        use crate::state::IndexService;
+        use tonic_health::pb::{HealthCheckRequest, health_check_response::ServingStatus, health_server::Health};
+        use tonic_health::server::HealthService;
+
+        let reporter = HealthReporter::new();
+        reporter.set_serving::<AnkrIndexerServer<IndexService>>().await;
+        let health = HealthService::from_health_reporter(reporter.clone());
+        let check = || async {
+            let request = tonic::Request::new(HealthCheckRequest {
+                service: "ankr.AnkrIndexer".into(),
+            });
+            health.check(request).await.unwrap().into_inner().status()
+        };
+        assert_eq!(check().await, ServingStatus::Serving);
+
+        let shutting_down = Arc::new(AtomicBool::new(false));
+        let draining = Arc::new(AtomicBool::new(false));
+        let shutdown = tokio::spawn(graceful_shutdown::<AnkrIndexerServer<IndexService>>(
+            async {},
+            reporter,
+            shutting_down.clone(),
+            draining.clone(),
+            Duration::from_millis(300),
+        ));
+
+        // 宽限期内已报告 NOT_SERVING，但尚未开始排空
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(check().await, ServingStatus::NotServing);
+        assert!(shutting_down.load(Ordering::Acquire));
+        assert!(!draining.load(Ordering::Acquire));
+
+        shutdown.await.unwrap();
+        assert!(draining.load(Ordering::Acquire));
+    }
+}
@@ -1,38 +1,112 @@
+use crate::config::{Config, IndexerConfig};
 use crate::db::PostgresDb;
+use crate::error::Result;
+use crate::keypool::ApiKeyPool;
+use crate::metrics::PrometheusMetrics;
+use crate::pb::ankr::{HotAssetList, TxHistoryList};
+use crate::shutdown::InFlightLayer;
+use crate::telemetry::LogSampler;
+use moka::future::Cache;
 use reqwest::Client;
-use std::env;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
+use tonic::metadata::MetadataMap;
 use tracing::info;
 
+// 索引查询结果缓存：key 为请求参数的哈希，value 为响应消息及其 metadata
+pub type ResponseCache<T> = Cache<String, Arc<(T, MetadataMap)>>;
+
+fn response_cache<T: Clone + Send + Sync + 'static>(config: &IndexerConfig) -> ResponseCache<T> {
+    Cache::builder()
+        .max_capacity(config.cache_max_entries)
+        .time_to_live(config.cache_ttl)
+        .build()
+}
+
+/// 按子系统划分的维护开关，开启后对应请求直接返回 503 / unavailable 并带 Retry-After
+#[derive(Debug)]
+pub struct Maintenance {
+    pub indexer: AtomicBool,
+    pub retry_after: Duration,
+}
+
+impl Maintenance {
+    // 当前仅有索引服务一个子系统
+    pub const SUBSYSTEMS: &'static [&'static str] = &["indexer"];
+
+    fn new(retry_after: Duration) -> Self {
+        Self {
+            indexer: AtomicBool::new(false),
+            retry_after,
+        }
+    }
+
+    fn flag(&self, subsystem: &str) -> Option<&AtomicBool> {
+        match subsystem {
+            "indexer" => Some(&self.indexer),
+            _ => None,
+        }
+    }
+
+    pub fn is_enabled(&self, subsystem: &str) -> bool {
+        self.flag(subsystem).is_some_and(|f| f.load(Ordering::Acquire))
+    }
+
+    pub fn any_enabled(&self) -> bool {
+        Self::SUBSYSTEMS.iter().any(|s| self.is_enabled(s))
+    }
+
+    // 返回 false 表示子系统不存在
+    pub fn set(&self, subsystem: &str, enabled: bool) -> bool {
+        match self.flag(subsystem) {
+            Some(flag) => {
+                flag.store(enabled, Ordering::Release);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct AppState {
-    pub ankr_key: String,      // 改为 String 类型
+    pub config: Arc<Config>,
+    pub ankr_keys: Arc<ApiKeyPool>,
     pub client: Arc<Client>,
     pub db: PostgresDb,
+    pub metrics: PrometheusMetrics,
+    pub indexer: IndexerConfig,
+    pub tx_cache: ResponseCache<TxHistoryList>,
+    pub asset_cache: ResponseCache<HotAssetList>,
+    pub log_sampler: Arc<LogSampler>,
+    pub maintenance: Arc<Maintenance>,
+    // 进行中的 gRPC 请求计数（含流式响应的后台任务），关闭时等待其归零
+    pub in_flight: InFlightLayer,
 }
 
 impl AppState {
-    pub fn new() -> Self {
-        dotenvy::dotenv().ok();
-        let ankr_key = env::var("ANKR_API_KEY").unwrap_or_default();
-        let db_url = env::var("DATABASE_URL").unwrap_or_default();
-        let db = PostgresDb::new(db_url);
-        let client = Client::builder()
-            .use_rustls_tls()
-            .pool_max_idle_per_host(10)
-            .http2_keep_alive_timeout(Duration::from_secs(30))
-            .timeout(Duration::from_secs(10))
-            .gzip(true)
-            .brotli(true)
-            .build()
-            .expect("Failed to build reqwest client");
-        info!("Built reqwest client with rustls TLS");   
-        AppState {
-            ankr_key,              // 直接使用 String
+    pub fn new(config: &Config) -> Result<Self> {
+        let db = PostgresDb::new(config.database_url.clone());
+        let client = config.http_client.build_client()?;
+        info!(http_config = ?config.http_client, "Built reqwest client with rustls TLS");
+        Ok(AppState {
+            config: Arc::new(config.clone()),
+            ankr_keys: Arc::new(ApiKeyPool::new(
+                config.ankr_multichain_url.clone(),
+                config.ankr_api_keys.clone(),
+                config.ankr_key_cooldown,
+            )),
             client: Arc::new(client),
-            db         // 直接使用 String
-        }
+            db,         // 直接使用 String
+            metrics: PrometheusMetrics::new()?,
+            indexer: config.indexer.clone(),
+            tx_cache: response_cache(&config.indexer),
+            asset_cache: response_cache(&config.indexer),
+            log_sampler: Arc::new(LogSampler::new(config.log_sampling.clone())),
+            maintenance: Arc::new(Maintenance::new(config.maintenance_retry_after)),
+            in_flight: InFlightLayer::new(),
+        })
     }
 }
 
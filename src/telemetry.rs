@@ -0,0 +1,106 @@
+// src/telemetry.rs
+use crate::config::LogSamplingConfig;
+use crate::error::{AppError, Result};
+use dashmap::DashMap;
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{Resource, trace::SdkTracerProvider};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tracing_subscriber::{EnvFilter, layer::SubscriberExt, util::SubscriberInitExt};
+
+const SERVICE_NAME: &str = "zeno-gateway";
+
+/// 初始化 tracing：日志级别由 RUST_LOG 控制（默认 info）；
+/// 配置了 OTLP endpoint 时额外导出 span，返回的 provider 需在退出前 shutdown 以刷新缓冲
+pub fn init_tracing(otlp_endpoint: &str) -> Result<Option<SdkTracerProvider>> {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+
+    let provider = if otlp_endpoint.is_empty() {
+        None
+    } else {
+        let exporter = SpanExporter::builder()
+            .with_tonic()
+            .with_endpoint(otlp_endpoint)
+            .build()
+            .map_err(|e| AppError::Custom(format!("failed to build OTLP exporter: {}", e)))?;
+        Some(
+            SdkTracerProvider::builder()
+                .with_batch_exporter(exporter)
+                .with_resource(Resource::builder().with_service_name(SERVICE_NAME).build())
+                .build(),
+        )
+    };
+
+    let otel_layer = provider
+        .as_ref()
+        .map(|p| tracing_opentelemetry::layer().with_tracer(p.tracer(SERVICE_NAME)));
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer())
+        .with(otel_layer)
+        .init();
+
+    Ok(provider)
+}
+
+/// 按路由采样请求日志：失败请求总是记录，成功请求每 N 条记录一条。
+/// 被采样掉的请求只是不打日志，指标照常统计。
+#[derive(Debug)]
+pub struct LogSampler {
+    config: LogSamplingConfig,
+    counters: DashMap<String, AtomicU64>,
+}
+
+impl LogSampler {
+    pub fn new(config: LogSamplingConfig) -> Self {
+        Self {
+            config,
+            counters: DashMap::new(),
+        }
+    }
+
+    pub fn should_log(&self, route: &str, is_error: bool) -> bool {
+        if is_error {
+            return true;
+        }
+        let rate = self
+            .config
+            .routes
+            .get(route)
+            .copied()
+            .unwrap_or(self.config.default_rate);
+        if rate <= 1 {
+            return true;
+        }
+        let seen = match self.counters.get(route) {
+            Some(counter) => counter.fetch_add(1, Ordering::Relaxed),
+            None => self
+                .counters
+                .entry(route.to_string())
+                .or_default()
+                .fetch_add(1, Ordering::Relaxed),
+        };
+        seen % rate == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn sampler_logs_one_in_n_successes_and_every_error() {
+        let sampler = LogSampler::new(LogSamplingConfig {
+            default_rate: 1,
+            routes: HashMap::from([("get_asset_balance".to_string(), 10)]),
+        });
+
+        let logged = (0..100).filter(|_| sampler.should_log("get_asset_balance", false)).count();
+        assert_eq!(logged, 10);
+        assert!((0..20).all(|_| sampler.should_log("get_asset_balance", true)));
+        // 未覆盖的路由使用默认采样率
+        assert!((0..20).all(|_| sampler.should_log("/health", false)));
+    }
+}
@@ -0,0 +1,92 @@
+// src/test_support.rs
+// 测试辅助：基于 wiremock 的上游 mock，以及指向 mock 的 AppState / IndexService
+use crate::config::Config;
+use crate::state::{AppState, IndexService};
+use serde_json::Value;
+use std::sync::Arc;
+use wiremock::matchers::{body_partial_json, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+// 测试配置使用的唯一 API key，上游请求路径即 /{TEST_API_KEY}
+pub const TEST_API_KEY: &str = "test-key";
+
+/// 模拟 Ankr 多链 API：按请求 body 片段匹配并返回预设的 JSON 或状态码
+/// 同一请求匹配多个响应时先注册的优先，用完次数的响应不再参与匹配
+pub struct MockUpstream {
+    server: MockServer,
+}
+
+impl MockUpstream {
+    pub async fn start() -> Self {
+        Self {
+            server: MockServer::start().await,
+        }
+    }
+
+    /// 作为 ANKR_MULTICHAIN_URL 的基础地址
+    pub fn base_url(&self) -> String {
+        format!("{}/", self.server.uri())
+    }
+
+    fn mock(key: &str, fragment: Value) -> wiremock::MockBuilder {
+        Mock::given(method("POST"))
+            .and(path(format!("/{key}")))
+            .and(body_partial_json(fragment))
+    }
+
+    /// 发往 /{key} 且 body 包含 fragment 的请求总是返回 template
+    pub async fn respond_with_for_key(&self, key: &str, fragment: Value, template: ResponseTemplate) {
+        Self::mock(key, fragment).respond_with(template).mount(&self.server).await;
+    }
+
+    /// body 包含 fragment 的请求总是返回 response
+    pub async fn respond_json(&self, fragment: Value, response: Value) {
+        self.respond_with_for_key(TEST_API_KEY, fragment, ResponseTemplate::new(200).set_body_json(response))
+            .await;
+    }
+
+    /// body 包含 fragment 的请求返回 times 次指定状态码（空 body）
+    pub async fn respond_status(&self, fragment: Value, status: u16, times: u64) {
+        Self::mock(TEST_API_KEY, fragment)
+            .respond_with(ResponseTemplate::new(status))
+            .up_to_n_times(times)
+            .mount(&self.server)
+            .await;
+    }
+
+    /// 已收到的全部请求 body，按到达顺序
+    pub async fn request_bodies(&self) -> Vec<Value> {
+        self.server
+            .received_requests()
+            .await
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|req| serde_json::from_slice(&req.body).ok())
+            .collect()
+    }
+
+    /// 指向 mock 的测试配置：单个 API key，上游白名单只放行本机
+    pub fn config(&self) -> Config {
+        let mut config = Config {
+            ankr_api_keys: vec![TEST_API_KEY.into()],
+            ankr_multichain_url: self.base_url(),
+            ..Config::default()
+        };
+        config.http_client.allowed_hosts = vec!["127.0.0.1".into()];
+        config
+    }
+
+    /// 以 config() 为基础，经 customize 调整后构建 IndexService
+    pub fn index_service_with(&self, customize: impl FnOnce(&mut Config)) -> IndexService {
+        let mut config = self.config();
+        customize(&mut config);
+        config.validate().expect("invalid test config");
+        IndexService {
+            state: Arc::new(AppState::new(&config).expect("failed to build test AppState")),
+        }
+    }
+
+    pub fn index_service(&self) -> IndexService {
+        self.index_service_with(|_| {})
+    }
+}
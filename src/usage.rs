@@ -0,0 +1,214 @@
+// src/usage.rs
+use crate::db::PostgresDb;
+use crate::error::Result;
+use moka::future::Cache;
+use moka::notification::{ListenerFuture, RemovalCause};
+use once_cell::sync::OnceCell;
+use sha2::{Digest, Sha256};
+use sqlx::types::chrono::Utc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tonic::Status;
+use tracing::warn;
+
+// 单个调用方在某个月的计数：total 含已持久化部分，pending 为尚未写库的增量
+#[derive(Debug, Default)]
+struct UsageCounter {
+    total: AtomicU64,
+    pending: AtomicU64,
+}
+
+type UsageKey = (String, String);
+
+/// 按调用方统计月度请求数：计数保存在内存中，定期批量 upsert 到 Postgres；
+/// monthly_cap 为 0 时只计数不限制。内存中最多保留 max_identities 个计数，
+/// 被淘汰的计数在淘汰时写库，再次出现时重新加载
+#[derive(Debug)]
+pub struct UsageTracker {
+    monthly_cap: u64,
+    db: Option<PostgresDb>,
+    counters: Cache<UsageKey, Arc<UsageCounter>>,
+}
+
+// 月份键，例如 "2026-10"；跨月后自动使用新的计数
+fn current_month() -> String {
+    Utc::now().format("%Y-%m").to_string()
+}
+
+// 写入一个计数的待持久化增量；失败时放回，等待下次 flush
+async fn persist_pending(db: &PostgresDb, identity_hash: &str, month: &str, counter: &UsageCounter) -> bool {
+    let delta = counter.pending.swap(0, Ordering::AcqRel);
+    if delta == 0 {
+        return true;
+    }
+    match db.add_monthly_usage(identity_hash, month, delta).await {
+        Ok(()) => true,
+        Err(e) => {
+            warn!(error = %e, "failed to persist monthly usage");
+            counter.pending.fetch_add(delta, Ordering::AcqRel);
+            false
+        }
+    }
+}
+
+impl UsageTracker {
+    pub fn new(monthly_cap: u64, max_identities: u64, db: Option<PostgresDb>) -> Self {
+        let listener_db = db.clone();
+        let counters = Cache::builder()
+            .max_capacity(max_identities)
+            // 容量淘汰或 flush 清理旧月份时，把尚未写库的增量写入
+            .async_eviction_listener(move |key: Arc<UsageKey>, counter: Arc<UsageCounter>, cause| {
+                let db = listener_db.clone();
+                Box::pin(async move {
+                    if cause == RemovalCause::Replaced {
+                        return;
+                    }
+                    if let Some(db) = db {
+                        persist_pending(&db, &key.0, &key.1, &counter).await;
+                    }
+                }) as ListenerFuture
+            })
+            .build();
+        Self {
+            monthly_cap,
+            db,
+            counters,
+        }
+    }
+
+    // 取得计数器；首次访问时立即返回从 0 开始的计数，当月已持久化的计数在后台加载后累加，
+    // 不在请求路径上等待数据库（加载完成前配额检查可能略为宽松）
+    async fn counter(&self, identity_hash: &str, month: &str) -> Arc<UsageCounter> {
+        let key = (identity_hash.to_string(), month.to_string());
+        self.counters
+            .get_with(key, async {
+                let counter = Arc::new(UsageCounter::default());
+                if let Some(db) = self.db.clone() {
+                    let counter = counter.clone();
+                    let (identity_hash, month) = (identity_hash.to_string(), month.to_string());
+                    tokio::spawn(async move {
+                        match db.load_monthly_usage(&identity_hash, &month).await {
+                            Ok(persisted) => {
+                                counter.total.fetch_add(persisted, Ordering::AcqRel);
+                            }
+                            Err(e) => warn!(error = %e, "failed to load monthly usage, counting from zero"),
+                        }
+                    });
+                }
+                counter
+            })
+            .await
+    }
+
+    /// 计入一次请求；超过月度上限时返回 resource_exhausted（被拒绝的请求不计数）
+    pub async fn try_consume(&self, identity: &str) -> std::result::Result<(), Status> {
+        self.try_consume_in(identity, &current_month()).await
+    }
+
+    async fn try_consume_in(&self, identity: &str, month: &str) -> std::result::Result<(), Status> {
+        let identity_hash = hex::encode(Sha256::digest(identity.as_bytes()));
+        let counter = self.counter(&identity_hash, month).await;
+        let cap = self.monthly_cap;
+        counter
+            .total
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |total| {
+                (cap == 0 || total < cap).then_some(total + 1)
+            })
+            .map_err(|_| {
+                Status::resource_exhausted(format!("Monthly quota of {} requests exceeded", cap))
+            })?;
+        counter.pending.fetch_add(1, Ordering::AcqRel);
+        Ok(())
+    }
+
+    /// 把待写入的增量写库，并清理已过去月份的计数
+    pub async fn flush(&self) {
+        let month = current_month();
+        for (key, counter) in self.counters.iter() {
+            if let Some(db) = &self.db
+                && !persist_pending(db, &key.0, &key.1, &counter).await
+            {
+                continue;
+            }
+            if key.1 != month {
+                self.counters.invalidate(key.as_ref()).await;
+            }
+        }
+        self.counters.run_pending_tasks().await;
+    }
+}
+
+// 全局用量统计，由限流拦截器使用；未初始化时不计数
+static USAGE_TRACKER: OnceCell<Arc<UsageTracker>> = OnceCell::new();
+
+pub fn init_usage_tracker(tracker: Arc<UsageTracker>) {
+    let _ = USAGE_TRACKER.set(tracker);
+}
+
+/// 拦截器入口：计入一次请求并检查月度配额
+pub async fn check_monthly_quota(identity: &str) -> std::result::Result<(), Status> {
+    match USAGE_TRACKER.get() {
+        Some(tracker) => tracker.try_consume(identity).await,
+        None => Ok(()),
+    }
+}
+
+/// 定期把用量增量写库
+pub async fn usage_flush_task(tracker: Arc<UsageTracker>, period: Duration) -> Result<()> {
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+        tracker.flush().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn requests_over_monthly_cap_are_rejected() {
+        let tracker = UsageTracker::new(3, 100, None);
+        for _ in 0..3 {
+            tracker.try_consume_in("client-a", "2026-10").await.unwrap();
+        }
+        let status = tracker.try_consume_in("client-a", "2026-10").await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+        // 其他调用方不受影响
+        tracker.try_consume_in("client-b", "2026-10").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn new_month_starts_a_fresh_count() {
+        let tracker = UsageTracker::new(2, 100, None);
+        for _ in 0..2 {
+            tracker.try_consume_in("client-a", "2026-09").await.unwrap();
+        }
+        assert!(tracker.try_consume_in("client-a", "2026-09").await.is_err());
+        tracker.try_consume_in("client-a", "2026-10").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn zero_cap_only_counts() {
+        let tracker = UsageTracker::new(0, 100, None);
+        for _ in 0..10 {
+            tracker.try_consume_in("client-a", "2026-10").await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn flush_drops_past_months_and_counters_stay_bounded() {
+        let tracker = UsageTracker::new(0, 10, None);
+        tracker.try_consume_in("client-a", "2000-01").await.unwrap();
+        tracker.try_consume("client-a").await.unwrap();
+        tracker.flush().await;
+        assert_eq!(tracker.counters.entry_count(), 1);
+
+        for i in 0..100 {
+            tracker.try_consume(&format!("client-{i}")).await.unwrap();
+        }
+        tracker.counters.run_pending_tasks().await;
+        assert!(tracker.counters.entry_count() <= 10);
+    }
+}
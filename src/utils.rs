@@ -1,63 +1,200 @@
-use tonic::{Request, transport::server::TcpConnectInfo};
+use axum::http::HeaderMap;
+use once_cell::sync::OnceCell;
+use std::net::SocketAddr;
+use sha3::{Digest, Keccak256};
+use tonic::{Request, Status, transport::server::TcpConnectInfo};
 use rustls::ServerConfig;
+use x509_parser::{certificate::X509Certificate, extensions::GeneralName, prelude::FromDer};
 use crate::error::Result;
+// 需要从日志和返回给客户端的错误信息中抹除的敏感值（如嵌在上游 URL 中的 API key）
+static SECRETS: OnceCell<Vec<String>> = OnceCell::new();
+
+/// 启动时注册敏感值，空字符串会被忽略
+pub fn init_secrets(secrets: Vec<String>) {
+    let _ = SECRETS.set(secrets.into_iter().filter(|s| !s.is_empty()).collect());
+}
+
+/// 将文本中出现的已注册敏感值替换为 `***`
+pub fn redact(text: &str) -> String {
+    let mut out = text.to_string();
+    for secret in SECRETS.get().into_iter().flatten() {
+        out = out.replace(secret.as_str(), "***");
+    }
+    out
+}
+
+/// 遮蔽敏感值，仅保留末 4 位用于辨认（如 `***1234`）；过短的值整体遮蔽
+pub fn mask_secret(secret: &str) -> String {
+    if secret.is_empty() {
+        return String::new();
+    }
+    let chars: Vec<char> = secret.chars().collect();
+    if chars.len() <= 8 {
+        return "***".to_string();
+    }
+    let tail: String = chars[chars.len() - 4..].iter().collect();
+    format!("***{}", tail)
+}
+
+/// EVM 地址格式校验：`0x` 前缀 + 40 位十六进制（不校验 EIP-55 大小写）
+pub fn validate_address(address: &str) -> std::result::Result<(), Status> {
+    let valid = address
+        .strip_prefix("0x")
+        .or_else(|| address.strip_prefix("0X"))
+        .is_some_and(|hex| hex.len() == 40 && hex.bytes().all(|b| b.is_ascii_hexdigit()));
+    if valid {
+        Ok(())
+    } else {
+        Err(Status::invalid_argument(format!(
+            "Invalid address {:?}: expected 0x followed by 40 hex characters",
+            address
+        )))
+    }
+}
+
+/// 地址规范化：统一转为小写，作为上游参数与缓存 key，避免大小写不同的同一地址缓存未命中。
+/// strict 为 true 时，大小写混合的地址必须通过 EIP-55 校验（全小写 / 全大写视为未带校验和）
+pub fn normalize_address(address: &str, strict: bool) -> std::result::Result<String, Status> {
+    validate_address(address)?;
+    let hex = &address[2..];
+    let has_lower = hex.bytes().any(|b| b.is_ascii_lowercase());
+    let has_upper = hex.bytes().any(|b| b.is_ascii_uppercase());
+    let lower = hex.to_ascii_lowercase();
+    if strict && has_lower && has_upper && eip55_checksum(&lower) != hex {
+        return Err(Status::invalid_argument(format!(
+            "Invalid address {:?}: EIP-55 checksum mismatch",
+            address
+        )));
+    }
+    Ok(format!("0x{}", lower))
+}
+
+// EIP-55：对小写 hex 做 keccak256，对应半字节 >= 8 的字母位大写
+fn eip55_checksum(lower_hex: &str) -> String {
+    let hash = Keccak256::digest(lower_hex.as_bytes());
+    lower_hex
+        .chars()
+        .enumerate()
+        .map(|(i, c)| {
+            let nibble = (hash[i / 2] >> (if i % 2 == 0 { 4 } else { 0 })) & 0x0f;
+            if nibble >= 8 { c.to_ascii_uppercase() } else { c }
+        })
+        .collect()
+}
+
+/// 设备标识校验：接受标准 UUID（如 `550e8400-e29b-41d4-a716-446655440000`），
+/// 或 32~128 位的十六进制字符串（如设备公钥/哈希的 hex 编码）
+pub fn validate_device_id(id: &str) -> std::result::Result<(), Status> {
+    if uuid::Uuid::try_parse(id).is_ok() {
+        return Ok(());
+    }
+    let is_hex = id.bytes().all(|b| b.is_ascii_hexdigit());
+    if is_hex && (32..=128).contains(&id.len()) {
+        return Ok(());
+    }
+    Err(Status::invalid_argument(
+        "Invalid device id: expected a UUID or a 32-128 character hex string",
+    ))
+}
+
+/// 上游域名白名单匹配：host 与白名单中的域名完全一致，或是其子域名
+pub fn host_allowed(host: &str, allowed_hosts: &[String]) -> bool {
+    allowed_hosts.iter().any(|allowed| {
+        host == allowed
+            || host
+                .strip_suffix(allowed.as_str())
+                .is_some_and(|prefix| prefix.ends_with('.'))
+    })
+}
+
 /// 从 tonic 的 Request 中万无一失地提取真实客户端 IP
 /// 支持顺序：X-Forwarded-For > X-Real-IP > Forwarded > 直连对端IP
 pub fn extract_client_ip<T>(req: &Request<T>) -> String {
+    let header = |name: &str| req.metadata().get(name).and_then(|v| v.to_str().ok());
+    let peer = req
+        .extensions()
+        .get::<TcpConnectInfo>()
+        .and_then(|connect_info| connect_info.remote_addr);
+    client_ip_from(header, peer)
+}
+
+/// HTTP 端点使用的同一套客户端 IP 提取规则，与 gRPC 拦截器保持一致
+pub fn extract_http_client_ip(headers: &HeaderMap, peer: SocketAddr) -> String {
+    client_ip_from(|name| headers.get(name).and_then(|v| v.to_str().ok()), Some(peer))
+}
+
+fn client_ip_from<'a>(header: impl Fn(&str) -> Option<&'a str>, peer: Option<SocketAddr>) -> String {
     // 1. 优先读取标准 header（从右到左第一个可信 IP）
-    if let Some(xff) = req.metadata().get("x-forwarded-for") {
-        if let Ok(xff_str) = xff.to_str() {
-            // X-Forwarded-For: client_ip, proxy1, proxy2
-            let ips: Vec<&str> = xff_str.split(',').map(|s| s.trim()).collect();
-            if let Some(first) = ips.first() {
-                if let Ok(ip) = first.parse::<std::net::IpAddr>() {
-                    return ip.to_string();
-                }
-            }
+    if let Some(xff_str) = header("x-forwarded-for") {
+        // X-Forwarded-For: client_ip, proxy1, proxy2
+        let ips: Vec<&str> = xff_str.split(',').map(|s| s.trim()).collect();
+        if let Some(first) = ips.first()
+            && let Ok(ip) = first.parse::<std::net::IpAddr>()
+        {
+            return ip.to_string();
         }
     }
 
     // 2. X-Real-IP（Nginx/Traefik 常用）
-    if let Some(real_ip) = req.metadata().get("x-real-ip") {
-        if let Ok(s) = real_ip.to_str() {
-            if let Ok(ip) = s.trim().parse::<std::net::IpAddr>() {
-                return ip.to_string();
-            }
-        }
+    if let Some(s) = header("x-real-ip")
+        && let Ok(ip) = s.trim().parse::<std::net::IpAddr>()
+    {
+        return ip.to_string();
     }
 
     // 3. Forwarded 标准 header（RFC 7239）
-    if let Some(forwarded) = req.metadata().get("forwarded") {
-        if let Ok(s) = forwarded.to_str() {
-            // 示例: For="[2001:db8::1]:1234", for=192.0.2.60;proto=http;by=203.0.113.43
-            for pair in s.split(';') {
-                let pair = pair.trim();
-                if pair.to_lowercase().starts_with("for=") {
-                    let ip_part = pair[4..].trim_matches(|c| c == '"' || c == '[' || c == ']');
-                    // 可能带端口，如 192.0.2.1:54321 或 [2001:db8::1]:1234
-                    let ip = ip_part.split(':').next().unwrap_or(ip_part);
-                    if let Ok(addr) = ip.parse::<std::net::IpAddr>() {
-                        return addr.to_string();
-                    }
+    if let Some(s) = header("forwarded") {
+        // 示例: For="[2001:db8::1]:1234", for=192.0.2.60;proto=http;by=203.0.113.43
+        for pair in s.split(';') {
+            let pair = pair.trim();
+            if pair.to_lowercase().starts_with("for=") {
+                let ip_part = pair[4..].trim_matches(|c| c == '"' || c == '[' || c == ']');
+                // 可能带端口，如 192.0.2.1:54321 或 [2001:db8::1]:1234
+                let ip = ip_part.split(':').next().unwrap_or(ip_part);
+                if let Ok(addr) = ip.parse::<std::net::IpAddr>() {
+                    return addr.to_string();
                 }
             }
         }
     }
 
-    // 4. 最后兜底：tonic 内置的直连对端地址（本地调试或无代理时使用）
-    if let Some(connect_info) = req.extensions().get::<TcpConnectInfo>() {
-        if let Some(addr) = connect_info.remote_addr {
-            return addr.ip().to_string();
-        }
+    // 4. 最后兜底：直连对端地址（本地调试或无代理时使用）
+    if let Some(addr) = peer {
+        return addr.ip().to_string();
     }
 
     // 理论上走不到这里
     "0.0.0.0".to_string()
 }
 
+/// 从 mTLS 已验证的客户端证书中提取身份
+/// 优先使用 SAN（DNS / URI / Email），否则使用 Subject CN
+pub fn extract_client_cert_identity<T>(req: &Request<T>) -> Option<String> {
+    let certs = req.peer_certs()?;
+    let (_, cert) = X509Certificate::from_der(certs.first()?.as_ref()).ok()?;
+
+    if let Ok(Some(san)) = cert.subject_alternative_name() {
+        for name in &san.value.general_names {
+            match name {
+                GeneralName::DNSName(s) | GeneralName::URI(s) | GeneralName::RFC822Name(s) => {
+                    return Some(s.to_string());
+                }
+                _ => {}
+            }
+        }
+    }
+
+    cert.subject()
+        .iter_common_name()
+        .next()?
+        .as_str()
+        .ok()
+        .map(str::to_string)
+}
 
 /// 辅助函数：从内存字节构建 Rustls ServerConfig  
-pub fn load_rustls_config(cert: &[u8], key: &[u8]) -> Result<ServerConfig> {
+/// alpn 按优先级列出协商协议，如 `&[b"h2", b"http/1.1"]`；仅 gRPC 时传 `&[b"h2"]`
+pub fn load_rustls_config(cert: &[u8], key: &[u8], alpn: &[&[u8]]) -> Result<ServerConfig> {
     let mut cert_reader = std::io::Cursor::new(cert);
     let certs =
         rustls_pemfile::certs(&mut cert_reader).collect::<std::result::Result<Vec<_>, _>>()?;
@@ -75,7 +212,7 @@ pub fn load_rustls_config(cert: &[u8], key: &[u8]) -> Result<ServerConfig> {
         std::io::Error::new(std::io::ErrorKind::InvalidData, "No private keys found")
     })?;
 
-    let config = ServerConfig::builder()
+    let mut config = ServerConfig::builder()
         .with_no_client_auth()
         .with_single_cert(certs, private_key)
         .map_err(|e| {
@@ -84,6 +221,122 @@ pub fn load_rustls_config(cert: &[u8], key: &[u8]) -> Result<ServerConfig> {
                 format!("Failed to create server config: {}", e),
             )
         })?;
+    config.alpn_protocols = alpn.iter().map(|p| p.to_vec()).collect();
 
     Ok(config)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn device_id_accepts_uuids_and_long_hex_strings() {
+        for id in [
+            "550e8400-e29b-41d4-a716-446655440000",
+            "550E8400-E29B-41D4-A716-446655440000",
+            &"ab".repeat(16),
+            &"0f".repeat(64),
+        ] {
+            assert!(validate_device_id(id).is_ok(), "{id} should be accepted");
+        }
+    }
+
+    #[test]
+    fn device_id_rejects_short_long_and_non_hex_values() {
+        for id in [
+            "",
+            "550e8400",
+            &"a".repeat(31),
+            &"a".repeat(129),
+            &format!("{}g", "a".repeat(31)),
+            "550e8400-e29b-41d4-a716-44665544000z",
+        ] {
+            let status = validate_device_id(id).unwrap_err();
+            assert_eq!(status.code(), tonic::Code::InvalidArgument, "{id}");
+        }
+    }
+
+    #[test]
+    fn redact_masks_registered_secrets_only() {
+        // 空字符串会被忽略，否则每个字符之间都会插入 ***
+        init_secrets(vec!["upstream-secret-key".into(), String::new()]);
+        assert_eq!(
+            redact("POST https://rpc.ankr.com/multichain/upstream-secret-key failed: upstream-secret-key"),
+            "POST https://rpc.ankr.com/multichain/*** failed: ***"
+        );
+        assert_eq!(redact("no secrets here"), "no secrets here");
+    }
+
+    #[test]
+    fn mask_secret_keeps_only_the_last_four_characters() {
+        assert_eq!(mask_secret(""), "");
+        assert_eq!(mask_secret("short"), "***");
+        assert_eq!(mask_secret("12345678"), "***");
+        assert_eq!(mask_secret("123456789"), "***6789");
+        assert_eq!(mask_secret("postgres://user:pass@db/app"), "***/app");
+        // 按字符而非字节截取，多字节字符不会 panic
+        assert_eq!(mask_secret("密钥密钥密钥密钥密钥"), "***密钥密钥");
+    }
+
+    // EIP-55 规范中的示例地址
+    const CHECKSUMMED: [&str; 4] = [
+        "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+        "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359",
+        "0xdbF03B407c01E7cD3CBea99509d93f8DDDC8C6FB",
+        "0xD1220A0cf47c7B9Be7A2E6BA89F429762e7b9aDb",
+    ];
+
+    #[test]
+    fn eip55_checksum_matches_the_spec_vectors() {
+        for address in CHECKSUMMED {
+            assert_eq!(eip55_checksum(&address[2..].to_ascii_lowercase()), address[2..]);
+        }
+    }
+
+    #[test]
+    fn normalize_address_lowercases_and_checks_mixed_case_when_strict() {
+        for address in CHECKSUMMED {
+            let lower = address.to_ascii_lowercase();
+            assert_eq!(normalize_address(address, true).unwrap(), lower);
+            assert_eq!(normalize_address(&lower, true).unwrap(), lower);
+            // 全大写视为未带校验和
+            let upper = format!("0x{}", address[2..].to_ascii_uppercase());
+            assert_eq!(normalize_address(&upper, true).unwrap(), lower);
+        }
+        let upper_prefix = "0X5AAEB6053F3E94C9B9A09F33669435E7EF1BEAED";
+        assert_eq!(normalize_address(upper_prefix, false).unwrap(), CHECKSUMMED[0].to_ascii_lowercase());
+
+        // 校验和错误：只在 strict 模式下拒绝
+        let wrong = "0x5AAeb6053F3E94C9b9A09f33669435E7Ef1BeAed";
+        assert_eq!(normalize_address(wrong, true).unwrap_err().code(), tonic::Code::InvalidArgument);
+        assert_eq!(normalize_address(wrong, false).unwrap(), CHECKSUMMED[0].to_ascii_lowercase());
+    }
+
+    #[test]
+    fn normalize_address_rejects_malformed_addresses() {
+        for address in [
+            "",
+            "0x",
+            "5aaeb6053f3e94c9b9a09f33669435e7ef1beaed",
+            "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beae",
+            "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaedd",
+            "0xzaaeb6053f3e94c9b9a09f33669435e7ef1beaed",
+        ] {
+            assert!(normalize_address(address, false).is_err(), "{address:?} should be rejected");
+        }
+    }
+
+    #[test]
+    fn host_allowed_matches_exact_hosts_and_subdomains_only() {
+        let allowed = vec!["ankr.com".to_string(), "blastapi.io".to_string()];
+        for host in ["ankr.com", "rpc.ankr.com", "eth.rpc.ankr.com", "blastapi.io"] {
+            assert!(host_allowed(host, &allowed), "{host} should be allowed");
+        }
+        // 仅以白名单域名结尾、但不是其子域名的 host 不放行
+        for host in ["evilankr.com", "ankr.com.evil.io", "notblastapi.io", "com", ""] {
+            assert!(!host_allowed(host, &allowed), "{host} should be rejected");
+        }
+        assert!(!host_allowed("rpc.ankr.com", &[]));
+    }
+}